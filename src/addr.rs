@@ -0,0 +1,241 @@
+//! Formatting and parsing of `host:port` strings, including IPv6.
+//!
+//! IPv6 literals are written in brackets, `[2001:db8::1]:9000`. Link-local
+//! addresses carry a scope, either an interface index or name, as in
+//! `[fe80::1%eth0]:9000`.
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::mdns;
+
+/// How long to wait for an mDNS answer when resolving a `.local` name.
+const MDNS_RESOLVE_WAIT: Duration = Duration::from_secs(2);
+
+/// Join a host and a port, putting IPv6 literals in brackets.
+/// # Example
+/// ```
+/// # use deliver::addr::join_host_port;
+/// assert_eq!(join_host_port("10.0.0.2", 9000), "10.0.0.2:9000");
+/// assert_eq!(join_host_port("fe80::1%eth0", 9000), "[fe80::1%eth0]:9000");
+/// ```
+pub fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Split `host:port` or `[host]:port` into its parts, without brackets.
+/// # Example
+/// ```
+/// # use deliver::addr::split_host_port;
+/// assert_eq!(split_host_port("[::1]:9000"), Some(("::1".to_string(), 9000)));
+/// assert_eq!(split_host_port("laptop.local:9000"), Some(("laptop.local".to_string(), 9000)));
+/// ```
+pub fn split_host_port(addr: &str) -> Option<(String, u16)> {
+    let (host, port) = addr.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = match host.strip_prefix('[') {
+        Some(inner) => inner.strip_suffix(']')?,
+        // An IPv6 literal without brackets cannot carry a port
+        None if host.contains(':') => return None,
+        None => host,
+    };
+    Some((host.to_string(), port))
+}
+
+/// Strip the brackets some users put around IPv6 literals.
+pub fn normalize_host(host: &str) -> String {
+    host.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string()
+}
+
+/// The host part of a socket address, with the scope of link-local IPv6
+/// addresses, so it can be joined with a port again later.
+pub fn host_of(addr: &SocketAddr) -> String {
+    match addr {
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => format!("{}%{}", v6.ip(), v6.scope_id()),
+        _ => addr.ip().to_string(),
+    }
+}
+
+/// Parse an IP literal, with an optional `%scope` on IPv6 addresses.
+pub fn parse_ip(host: &str, port: u16) -> Option<SocketAddr> {
+    let (ip, scope) = match host.split_once('%') {
+        Some((ip, scope)) => (ip, Some(scope)),
+        None => (host, None),
+    };
+
+    match (ip.parse::<IpAddr>().ok()?, scope) {
+        (ip, None) => Some(SocketAddr::new(ip, port)),
+        (IpAddr::V6(ip), Some(scope)) => {
+            let scope_id = scope_id(scope)?;
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+        }
+        (IpAddr::V4(_), Some(_)) => None,
+    }
+}
+
+/// Check that `host` is an IP literal or a well-formed host name,
+/// so typos are caught before anything is looked up or saved.
+/// # Example
+/// ```
+/// # use deliver::addr::validate_host;
+/// assert!(validate_host("192.168.1.20").is_ok());
+/// assert!(validate_host("fe80::1%1").is_ok());
+/// assert!(validate_host("laptop.local").is_ok());
+/// assert!(validate_host("192.168.1.300").is_err());
+/// assert!(validate_host("http://laptop").is_err());
+/// ```
+pub fn validate_host(host: &str) -> anyhow::Result<()> {
+    if host.is_empty() {
+        return Err(anyhow::anyhow!("The address is empty"));
+    }
+    if parse_ip(host, 0).is_some() {
+        return Ok(());
+    }
+    let (ip, _) = host.split_once('%').unwrap_or((host, ""));
+    if ip.contains(':')
+        && ip
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.')
+    {
+        return Err(anyhow::anyhow!("Invalid IPv6 address: {}", host));
+    }
+    if host.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(anyhow::anyhow!("Invalid IPv4 address: {}", host));
+    }
+
+    let name = host.strip_suffix('.').unwrap_or(host);
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if name.len() > 253 || !name.split('.').all(valid_label) {
+        return Err(anyhow::anyhow!("Invalid host name: {}", host));
+    }
+    Ok(())
+}
+
+/// Resolve `host:port` into socket addresses.
+/// IP literals, scoped or not, are taken as they are; anything else is
+/// looked up through the system resolver. `.local` names the system
+/// cannot resolve are looked up over mDNS.
+pub fn resolve(addr: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    let (host, port) =
+        split_host_port(addr).ok_or_else(|| anyhow::anyhow!("Invalid address: {}", addr))?;
+
+    if let Some(addr) = parse_ip(&host, port) {
+        return Ok(vec![addr]);
+    }
+    validate_host(&host)?;
+
+    let resolved = (host.as_str(), port)
+        .to_socket_addrs()
+        .map(|addrs| addrs.collect::<Vec<_>>());
+    match resolved {
+        Ok(addrs) if !addrs.is_empty() => Ok(addrs),
+        _ if is_mdns_name(&host) => {
+            let addrs: Vec<SocketAddr> = mdns::resolve_hostname(&host, MDNS_RESOLVE_WAIT)?
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect();
+            if addrs.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "No device named {} answered on the LAN",
+                    host
+                ));
+            }
+            Ok(addrs)
+        }
+        Ok(_) => Err(anyhow::anyhow!("{} did not resolve to any address", host)),
+        Err(e) => Err(anyhow::anyhow!("Could not resolve {}: {}", host, e)),
+    }
+}
+
+/// Whether `host` is a multicast DNS name, which lives under `.local`.
+fn is_mdns_name(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(".local")
+}
+
+/// Whether an IPv6 address is link-local, i.e. only valid together with a scope.
+pub fn is_link_local(ip: &Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xffc0) == 0xfe80
+}
+
+/// An interface index, given either as a number or as an interface name.
+fn scope_id(scope: &str) -> Option<u32> {
+    if let Ok(index) = scope.parse() {
+        return Some(index);
+    }
+
+    if_addrs::get_if_addrs()
+        .ok()?
+        .into_iter()
+        .find(|interface| interface.name == scope)
+        .and_then(|interface| interface.index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_and_join_round_trip() {
+        for (host, port) in [
+            ("10.0.0.2", 9000),
+            ("laptop.local", 1),
+            ("::1", 65535),
+            ("2001:db8::1", 9000),
+            ("fe80::1%eth0", 9000),
+            ("fe80::1%3", 9000),
+        ] {
+            let joined = join_host_port(host, port);
+            assert_eq!(
+                split_host_port(&joined),
+                Some((host.to_string(), port)),
+                "{}",
+                joined
+            );
+        }
+        assert_eq!(join_host_port("::1", 9000), "[::1]:9000");
+    }
+
+    #[test]
+    fn split_rejects_malformed_addresses() {
+        for addr in [
+            "",
+            "10.0.0.2",
+            "10.0.0.2:",
+            "10.0.0.2:port",
+            "10.0.0.2:65536",
+            "10.0.0.2:-1",
+            // Without brackets the last group is taken for the port
+            "::1:9000",
+            "2001:db8::1",
+            "[::1:9000",
+            "::1]:9000",
+            "[::1]9000",
+        ] {
+            assert_eq!(split_host_port(addr), None, "{:?}", addr);
+        }
+    }
+
+    #[test]
+    fn normalize_strips_brackets_and_whitespace() {
+        assert_eq!(normalize_host("[::1]"), "::1");
+        assert_eq!(normalize_host(" [fe80::1%eth0] "), "fe80::1%eth0");
+        assert_eq!(normalize_host("10.0.0.2"), "10.0.0.2");
+        assert_eq!(normalize_host("laptop.local"), "laptop.local");
+        assert_eq!(normalize_host(""), "");
+    }
+}
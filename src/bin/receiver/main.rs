@@ -3,39 +3,414 @@
 
 mod utils;
 
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
 use clap::Parser;
 use console::style;
 
-use crate::utils::{show_ipv4, tcp_listener};
+use deliver::addr::{host_of, normalize_host, parse_ip};
+use deliver::discovery::{self, Announcement};
+use deliver::identity::Identity;
+use deliver::known_peers::{KnownPeers, Trust};
+use deliver::mdns::Advertisement;
+use deliver::pkg_info::PkgInfo;
+use deliver::proto::CAPABILITIES;
+
+use crate::utils::config::{AcceptPolicy, ReceiverCfg};
+use crate::utils::limits::Limiter;
+use crate::utils::listen::{PortFallback, bind_listeners};
+use crate::utils::output::{FailedFiles, Output};
+use crate::utils::progress::Transfers;
+#[cfg(unix)]
+use crate::utils::report::notify_systemd;
+use crate::utils::report::{init_logger, is_headless, set_headless, status};
+use crate::utils::snippets::{SnippetMode, Snippets};
+use crate::utils::tui;
+use crate::utils::{Context, Ended, show_ip_addrs, show_qr, tcp_listener};
 
 #[derive(Parser, Debug)]
 #[command(version, author, about, long_about = None)]
 struct Args {
-    /// The port to listen on
-    #[arg(short, long, default_value_t = 9000)]
-    port: u16,
+    /// The port to listen on [default: 9000]
+    #[arg(short, long, env = "DELIVER_PORT")]
+    port: Option<u16>,
+
+    /// Listen on this local address only, may be given several times [default: all interfaces]
+    #[arg(
+        short,
+        long,
+        value_name = "ADDR",
+        env = "DELIVER_BIND",
+        value_delimiter = ','
+    )]
+    bind: Vec<String>,
+
+    /// What to do when the port is already in use [default: off]
+    #[arg(long, value_enum, env = "DELIVER_PORT_FALLBACK")]
+    port_fallback: Option<PortFallback>,
+
+    /// Which senders to accept [default: all]
+    #[arg(long, value_enum, env = "DELIVER_ACCEPT")]
+    accept: Option<AcceptPolicy>,
+
+    /// Only accept senders whose device key is marked `allow`, same as `--accept known`.
+    /// Takes precedence over --accept and DELIVER_ACCEPT
+    #[arg(long)]
+    known_only: bool,
+
+    /// The maximum number of transfers running at the same time [default: 4]
+    #[arg(long, env = "DELIVER_MAX_TRANSFERS")]
+    max_transfers: Option<usize>,
+
+    /// The maximum number of transfers running or queued from one source IP [default: 2]
+    #[arg(long, env = "DELIVER_MAX_PER_IP")]
+    max_per_ip: Option<usize>,
+
+    /// The number of connections that may wait for a free transfer slot [default: 16]
+    #[arg(long, env = "DELIVER_QUEUE_SIZE")]
+    queue_size: Option<usize>,
+
+    /// Seconds the sender may take for each handshake step [default: 30]
+    #[arg(long, value_name = "SECS", env = "DELIVER_HEADER_TIMEOUT")]
+    header_timeout: Option<u64>,
+
+    /// Seconds without any data before a transfer is aborted [default: 30]
+    #[arg(long, value_name = "SECS", env = "DELIVER_IDLE_TIMEOUT")]
+    idle_timeout: Option<u64>,
+
+    /// Bytes per second below which a transfer counts as stalled, 0 disables it [default: 1024]
+    #[arg(long, value_name = "BYTES", env = "DELIVER_MIN_THROUGHPUT")]
+    min_throughput: Option<u64>,
+
+    /// The directory to save received files in [default: the current directory]
+    #[arg(short, long, value_name = "DIR", env = "DELIVER_OUT_DIR")]
+    out: Option<PathBuf>,
+
+    /// Where to save each file below the output directory, e.g. "{date}/{peer}/{name}".
+    /// Placeholders: {name}, {stem}, {ext}, {peer}, {date}, {time} [default: {name}]
+    #[arg(long, value_name = "TEMPLATE", env = "DELIVER_TEMPLATE")]
+    template: Option<String>,
+
+    /// What to do with files that fail the checksum [default: quarantine]
+    #[arg(long, value_enum, env = "DELIVER_FAILED_FILES")]
+    failed_files: Option<FailedFiles>,
+
+    /// Where to move files that fail the checksum [default: OUT/.quarantine]
+    #[arg(long, value_name = "DIR", env = "DELIVER_QUARANTINE_DIR")]
+    quarantine_dir: Option<PathBuf>,
+
+    /// What to do with text snippets [default: print]
+    #[arg(long, value_enum, env = "DELIVER_SNIPPETS")]
+    snippets: Option<SnippetMode>,
+
+    /// Where to save text snippets [default: OUT/snippets.txt]
+    #[arg(long, value_name = "PATH", env = "DELIVER_SNIPPETS_FILE")]
+    snippets_file: Option<PathBuf>,
+
+    /// Run this shell command after a file was received and its checksum matched
+    #[arg(long, value_name = "COMMAND", env = "DELIVER_ON_SUCCESS")]
+    on_success: Option<String>,
+
+    /// Run this shell command after a file arrived with the wrong checksum
+    #[arg(long, value_name = "COMMAND", env = "DELIVER_ON_CHECKSUM_MISMATCH")]
+    on_checksum_mismatch: Option<String>,
+
+    /// Run this shell command after a transfer was rejected
+    #[arg(long, value_name = "COMMAND", env = "DELIVER_ON_REJECT")]
+    on_reject: Option<String>,
+
+    /// Seconds a hook may run before it is killed [default: 60]
+    #[arg(long, value_name = "SECS", env = "DELIVER_HOOK_TIMEOUT")]
+    hook_timeout: Option<u64>,
+
+    /// Print a QR code of the address senders can connect with
+    #[arg(long)]
+    qr: bool,
+
+    /// Do not answer LAN discovery probes or publish an mDNS service
+    #[arg(long, env = "DELIVER_NO_ANNOUNCE")]
+    no_announce: bool,
+
+    /// Run without a terminal, e.g. under systemd: no key handling or progress
+    /// bars, status goes to the log, and SIGTERM/SIGINT stop the server.
+    /// Implied when stdin is not a terminal
+    #[arg(long, visible_alias = "no-tty")]
+    daemon: bool,
+
+    /// Show a full-screen dashboard of transfers and accept prompts
+    /// instead of status lines
+    #[arg(long, conflicts_with = "daemon")]
+    tui: bool,
+
+    /// Exit after one transfer, same as `--count 1`
+    #[arg(long, conflicts_with = "count")]
+    once: bool,
+
+    /// Exit after this many transfers. The exit code tells how they ended:
+    /// 0 all succeeded, 2 checksum mismatch, 3 rejected, 4 timed out, 1 otherwise.
+    /// Connections closed before announcing a file do not count
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    count: Option<u64>,
+
+    /// Append the log to this file instead of writing it to stderr
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Mark a device fingerprint as allowed and exit
+    #[arg(long, value_name = "FINGERPRINT", conflicts_with = "deny")]
+    allow: Option<String>,
+
+    /// Mark a device fingerprint as denied and exit
+    #[arg(long, value_name = "FINGERPRINT")]
+    deny: Option<String>,
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     // ANCHOR: some init events
-    env_logger::init();
     let args = Args::parse();
+    // Raw mode and progress bars need a terminal
+    if args.daemon || !io::stdin().is_terminal() {
+        set_headless();
+    }
+    init_logger(args.log_file.as_deref())?;
     // ANCHOR_END: some init events
 
-    println!("{}", style("Starting server...".to_string()).bold().blue());
+    // ANCHOR: manage known peers
+    if let Some((fingerprint, trust)) = args
+        .allow
+        .clone()
+        .map(|fp| (fp, Trust::Allow))
+        .or(args.deny.clone().map(|fp| (fp, Trust::Deny)))
+    {
+        let mut known_peers = KnownPeers::load();
+        let name = known_peers
+            .get(&fingerprint)
+            .map(|peer| peer.name.clone())
+            .unwrap_or_default();
+        known_peers.set(&fingerprint, trust, &name);
+        known_peers.save();
+
+        println!("Marked {} as {}", fingerprint, style(trust).bold());
+        return Ok(ExitCode::SUCCESS);
+    }
+    // ANCHOR_END: manage known peers
+
+    let identity = Identity::load()?;
+
+    let board = match args.tui {
+        true if is_headless() => {
+            return Err(anyhow::anyhow!("The dashboard needs a terminal"));
+        }
+        true => Some(tui::enable()),
+        false => None,
+    };
+
+    status(style("Starting server...".to_string()).bold().blue());
 
     // ANCHOR: cfg info
-    let args_port = args.port;
-    let ip_addr = format!("0.0.0.0:{}", args_port);
+    // receiver.toml first, then the environment and the command line
+    let cfg = apply_args(ReceiverCfg::load()?, &args);
+    cfg.validate()?;
+    if cfg.accept == AcceptPolicy::Ask && board.is_none() {
+        return Err(anyhow::anyhow!(
+            "Asking before accepting needs the dashboard, start with --tui"
+        ));
+    }
+
+    let binds = cfg
+        .bind
+        .iter()
+        .map(|bind| {
+            parse_ip(&normalize_host(bind), 0)
+                .ok_or_else(|| anyhow::anyhow!("Invalid bind address: {}", bind))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let listeners = bind_listeners(&binds, cfg.port, cfg.port_fallback)?;
+    let listening = listeners
+        .iter()
+        .map(|listener| listener.local_addr())
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let port = listening[0].port();
+
+    let out_dir = cfg.out_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let snippets_file = cfg
+        .snippets_file
+        .clone()
+        .unwrap_or_else(|| out_dir.join("snippets.txt"));
+    let snippets = Snippets::new(cfg.snippets, snippets_file);
+    let output = Output::new(out_dir, &cfg.file_template)?
+        .with_failed_files(cfg.failed_files, cfg.quarantine_dir.clone());
     // ANCHOR_END: cfg info
 
-    show_ipv4();
+    if let Some(board) = &board {
+        let addresses = match binds.is_empty() {
+            true => vec![format!("all interfaces, port {}", port)],
+            false => listening.iter().map(|addr| addr.to_string()).collect(),
+        };
+        board.set_info(addresses, output.dir());
+    }
+
+    if binds.is_empty() {
+        show_ip_addrs();
+    } else {
+        for addr in &listening {
+            status(format!("Server IP: {}", host_of(addr)));
+        }
+    }
+    status(format!("Device fingerprint: {}", identity.fingerprint()));
+    status(format!("Saving files to: {}", output.dir().display()));
+    if args.qr
+        && let Err(e) = show_qr(&listening, &identity.fingerprint())
+    {
+        log::error!("Failed to draw the QR code: {}", e);
+    }
+
+    // ANCHOR: announce ourselves on the LAN
+    let mut _advertisement = None;
+    if cfg.announce {
+        let announcement = Announcement {
+            name: identity.device().name,
+            port,
+            version: PkgInfo::new().get_pkg_version().to_string(),
+            fingerprint: identity.fingerprint(),
+            capabilities: CAPABILITIES.iter().map(|cap| cap.to_string()).collect(),
+        };
+
+        // Stays published until the receiver exits
+        _advertisement = Advertisement::publish(&announcement)
+            .inspect_err(|e| log::error!("Failed to publish mDNS service: {}", e))
+            .ok();
+
+        tokio::spawn(async move {
+            if let Err(e) = discovery::serve(announcement).await {
+                log::error!("LAN discovery stopped: {}", e);
+            }
+        });
+    }
+    // ANCHOR_END: announce ourselves on the LAN
+
+    if port != cfg.port {
+        status(format!(
+            "Port {} is in use, using port {} instead",
+            cfg.port,
+            style(port).bold().yellow()
+        ));
+    }
+    let count = match args.once {
+        true => Some(1),
+        false => args.count.map(|count| count as usize),
+    };
+    let quit_hint = match is_headless() {
+        true => "",
+        false => " (press 'q' to quit)",
+    };
+    status(format!(
+        "Server listening on port {}...{}",
+        style(port).bold().green(),
+        quit_hint
+    ));
+    match count {
+        Some(1) => status("Exiting after the first transfer"),
+        Some(count) => status(format!("Exiting after {} transfers", count)),
+        None => {}
+    }
+
+    let ctx = Context {
+        identity,
+        accept: cfg.accept,
+        hooks: cfg.hooks,
+        limiter: Limiter::new(cfg.limits),
+        timeouts: cfg.timeouts,
+        transfers: Transfers::new(),
+        output,
+        snippets,
+        count,
+    };
+
+    #[cfg(unix)]
+    notify_systemd(&[
+        sd_notify::NotifyState::Ready,
+        sd_notify::NotifyState::Status(&format!("Listening on port {}", port)),
+    ]);
+
+    let ended = tcp_listener(listeners, ctx).await?;
+
+    // ANCHOR: exit with how the transfers ended
+    let Some(count) = count else {
+        return Ok(ExitCode::SUCCESS);
+    };
+    if ended.len() < count {
+        return Err(anyhow::anyhow!(
+            "Stopped after {} of {} transfers",
+            ended.len(),
+            count
+        ));
+    }
+    // The first transfer that did not succeed decides
+    let code = ended
+        .iter()
+        .map(|ended| ended.exit_code())
+        .find(|code| *code != Ended::Success.exit_code())
+        .unwrap_or_default();
+    Ok(ExitCode::from(code))
+    // ANCHOR_END: exit with how the transfers ended
+}
+
+/// Override the config file with the options given on the command line
+/// or in the environment.
+fn apply_args(mut cfg: ReceiverCfg, args: &Args) -> ReceiverCfg {
+    cfg.port = args.port.unwrap_or(cfg.port);
+    if !args.bind.is_empty() {
+        cfg.bind = args.bind.clone();
+    }
+    cfg.port_fallback = args.port_fallback.unwrap_or(cfg.port_fallback);
+    cfg.accept = if args.known_only {
+        AcceptPolicy::Known
+    } else {
+        args.accept.unwrap_or(cfg.accept)
+    };
+    if args.no_announce {
+        cfg.announce = false;
+    }
+
+    let limits = &mut cfg.limits;
+    limits.max_transfers = args.max_transfers.unwrap_or(limits.max_transfers);
+    limits.max_per_ip = args.max_per_ip.unwrap_or(limits.max_per_ip);
+    limits.queue_size = args.queue_size.unwrap_or(limits.queue_size);
+
+    let timeouts = &mut cfg.timeouts;
+    timeouts.header = args.header_timeout.unwrap_or(timeouts.header);
+    timeouts.idle = args.idle_timeout.unwrap_or(timeouts.idle);
+    timeouts.min_throughput = args.min_throughput.unwrap_or(timeouts.min_throughput);
 
-    println!(
-        "Server listening on port {}... (press 'q' to quit)",
-        style(args_port).bold().green()
-    );
+    if args.out.is_some() {
+        cfg.out_dir = args.out.clone();
+    }
+    if let Some(template) = &args.template {
+        cfg.file_template = template.clone();
+    }
+    cfg.failed_files = args.failed_files.unwrap_or(cfg.failed_files);
+    if args.quarantine_dir.is_some() {
+        cfg.quarantine_dir = args.quarantine_dir.clone();
+    }
+    cfg.snippets = args.snippets.unwrap_or(cfg.snippets);
+    if args.snippets_file.is_some() {
+        cfg.snippets_file = args.snippets_file.clone();
+    }
 
-    tcp_listener(&ip_addr).await
+    let hooks = &mut cfg.hooks;
+    for (hook, arg) in [
+        (&mut hooks.on_success, &args.on_success),
+        (&mut hooks.on_checksum_mismatch, &args.on_checksum_mismatch),
+        (&mut hooks.on_reject, &args.on_reject),
+    ] {
+        if arg.is_some() {
+            *hook = arg.clone();
+        }
+    }
+    hooks.timeout = args.hook_timeout.unwrap_or(hooks.timeout);
+    cfg
 }
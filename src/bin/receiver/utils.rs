@@ -1,23 +1,30 @@
-/// It will show the server's IPv4 address.
+pub mod config;
+pub mod hooks;
+pub mod limits;
+pub mod listen;
+pub mod output;
+pub mod progress;
+pub mod report;
+pub mod snippets;
+pub mod tui;
+
+/// It will show the server's IPv4 and IPv6 addresses.
 /// Including vurtual interfaces and physical interfaces.
-pub fn show_ipv4() {
+/// Link-local IPv6 addresses are shown with their scope.
+pub fn show_ip_addrs() {
     // ANCHOR: show the server's IP address
     match if_addrs::get_if_addrs() {
         Ok(interfaces) => {
             for interface in interfaces {
-                // Filter out loopback interfaces and non-IPv4 addresses
-                if !interface.is_loopback() {
-                    match interface.addr {
-                        if_addrs::IfAddr::V4(_) => {
-                            println!(
-                                "Server IP: {} - Interface: {}",
-                                interface.addr.ip(),
-                                interface.name
-                            );
-                        }
-                        _ => continue, // Only handle IPv4 addresses
-                    }
+                // Filter out loopback interfaces
+                if interface.is_loopback() {
+                    continue;
                 }
+                let ip = match interface.addr.ip() {
+                    IpAddr::V6(v6) if is_link_local(&v6) => format!("{}%{}", v6, interface.name),
+                    ip => ip.to_string(),
+                };
+                status(format!("Server IP: {} - Interface: {}", ip, interface.name));
             }
         }
         Err(e) => log::error!("Error retrieving interfaces: {}", e),
@@ -25,31 +32,203 @@ pub fn show_ipv4() {
     // ANCHOR_END: show the server's IP address
 }
 
+/// Draw a QR code of the `deliver://` URI senders can connect with.
+/// It holds the first usable address, preferring IPv4 over IPv6.
+/// # Arguments
+/// - `listening`: The local addresses of the server's listeners.
+/// - `fingerprint`: This device's fingerprint, so the sender can pin it.
+pub fn show_qr(listening: &[SocketAddr], fingerprint: &str) -> anyhow::Result<()> {
+    let Some(port) = listening.first().map(SocketAddr::port) else {
+        return Err(anyhow::anyhow!("Not listening anywhere"));
+    };
+
+    let interfaces = if_addrs::get_if_addrs()?;
+    let Some(host) = interfaces
+        .iter()
+        .filter(|interface| !interface.is_loopback())
+        // Only interfaces we listen on, unless bound to a wildcard address
+        .filter(|interface| {
+            listening
+                .iter()
+                .any(|addr| addr.ip().is_unspecified() || addr.ip() == interface.addr.ip())
+        })
+        .min_by_key(|interface| match interface.addr.ip() {
+            IpAddr::V4(_) => 0,
+            IpAddr::V6(v6) if !is_link_local(&v6) => 1,
+            IpAddr::V6(_) => 2,
+        })
+        .map(|interface| match interface.addr.ip() {
+            IpAddr::V6(v6) if is_link_local(&v6) => format!("{}%{}", v6, interface.name),
+            ip => ip.to_string(),
+        })
+    else {
+        return Err(anyhow::anyhow!(
+            "No network interface to put in the QR code"
+        ));
+    };
+
+    let uri = DeliverUri {
+        host,
+        port,
+        fingerprint: Some(fingerprint.to_string()),
+    };
+    if is_headless() || tui::board().is_some() {
+        status(format!("Connect with: {}", uri));
+        return Ok(());
+    }
+    let code = QrCode::new(uri.to_string())?;
+    // Inverted, so it scans on the usual dark terminal background
+    println!(
+        "{}",
+        code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build()
+    );
+    println!("Connect with: {}", style(uri).bold());
+    Ok(())
+}
+
 use crossterm::event::{self, Event, KeyCode};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-use tokio::time::{Duration, sleep};
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+
+/// State shared by all client handlers.
+pub struct Context {
+    /// This device's identity, presented to every sender.
+    pub identity: Identity,
+    /// Which devices are accepted.
+    pub accept: AcceptPolicy,
+    /// Commands run after each transfer.
+    pub hooks: Hooks,
+    pub limiter: Arc<Limiter>,
+    pub timeouts: Timeouts,
+    /// Where received files are saved.
+    pub output: Output,
+    /// Where text snippets go.
+    pub snippets: Snippets,
+    pub transfers: Arc<Transfers>,
+    /// Exit after this many transfers ended, see [`Ended`].
+    pub count: Option<usize>,
+}
+
+/// How a transfer ended, for the exit code of `--once` and `--count`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ended {
+    Success,
+    ChecksumMismatch,
+    Rejected,
+    TimedOut,
+    /// Any other failure, e.g. a truncated transfer.
+    Failed,
+}
+
+impl Ended {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Ended::Success => 0,
+            Ended::Failed => 1,
+            Ended::ChecksumMismatch => 2,
+            Ended::Rejected => 3,
+            Ended::TimedOut => 4,
+        }
+    }
+}
 
 /// TCP listener that handles incoming connections and allows quitting with 'q'
 /// It will save the file in the work dir.
+/// Headless, it quits on SIGTERM or SIGINT instead, after letting running
+/// transfers finish for a while. With the dashboard enabled, it runs the
+/// dashboard until either happens. With `ctx.count`, it also quits once
+/// that many transfers ended, and takes no more connections meanwhile.
 /// # Arguments
-/// - `ip_addr`: A string slice that holds the IP address and port of the server.
+/// - `listeners`: The bound listeners, see [`listen::bind_listeners`].
+/// - `ctx`: The state shared by all client handlers.
 /// # Returns
-/// An `anyhow::Result<()>` indicating success or failure.
-pub async fn tcp_listener(ip_addr: &str) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(ip_addr).await?;
+/// How each transfer ended, in the order they ended.
+pub async fn tcp_listener(listeners: Vec<TcpListener>, ctx: Context) -> anyhow::Result<Vec<Ended>> {
+    let ctx = Arc::new(ctx);
+
+    // Funnel the connections of all listeners into one queue
+    let (conn_tx, mut conn_rx) = mpsc::channel(16);
+    for listener in listeners {
+        let conn_tx = conn_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                if conn_tx.send(listener.accept().await).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
 
-    // Set raw mode for stdin to capture 'q' key press
-    enable_raw_mode()?;
+    let headless = is_headless();
+    let board = tui::board().cloned();
+    let dashboard = board
+        .clone()
+        .map(|board| tokio::task::spawn_blocking(move || tui::run(board)));
+    if !headless && board.is_none() {
+        // Set raw mode for stdin to capture 'q' key press
+        enable_raw_mode()?;
+    }
 
-    loop {
+    let quit = async {
+        if headless {
+            shutdown_signal().await
+        } else if let Some(board) = tui::board() {
+            tokio::select! {
+                signal = shutdown_signal() => signal,
+                _ = board.quit_requested() => Ok(Quit::Key),
+            }
+        } else {
+            tokio::select! {
+                signal = shutdown_signal() => signal,
+                key = quit_key() => key,
+            }
+        }
+    };
+    tokio::pin!(quit);
+
+    let mut clients = JoinSet::new();
+    let mut ended = Vec::new();
+    let quit_reason = loop {
+        // Leave further connections waiting once enough transfers are running
+        let wanted = ctx
+            .count
+            .is_none_or(|count| ended.len() + clients.len() < count);
         tokio::select! {
             // Accept incoming connections
-            connect = listener.accept() => {
+            Some(connect) = conn_rx.recv(), if wanted => {
                 match connect {
-                    Ok((stream, addr)) => {
-                        tokio::spawn(async move {
-                            if let Err(e) = handle_client(stream, addr).await {
-                                log::error!("Error handling client {}: {}", addr, e);
+                    Ok((mut stream, addr)) => {
+                        // Show IPv4 clients of the dual-stack socket as plain IPv4
+                        let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+                        let Some(mut admission) = ctx.limiter.admit(addr.ip()) else {
+                            status(format!("Busy, turning away {}", addr));
+                            tokio::spawn(async move {
+                                let busy = Frame::Busy { retry_after: BUSY_RETRY_AFTER };
+                                if let Err(e) = write_frame_async(&mut stream, &busy).await {
+                                    log::debug!("Failed to tell {} we are busy: {}", addr, e);
+                                }
+                            });
+                            continue;
+                        };
+
+                        let ctx = ctx.clone();
+                        clients.spawn(async move {
+                            // Keep the sender queued until a transfer slot is free
+                            let slot = keep_alive(&mut stream, ctx.timeouts.header(), admission.wait());
+                            if let Err(e) = slot.await {
+                                log::debug!("{} left the queue: {}", addr, e);
+                                return None;
+                            }
+                            match handle_client(stream, addr, ctx, admission).await {
+                                Ok(ended) => Some(ended),
+                                Err(e) => {
+                                    log::error!("Error handling client {}: {}", addr, e);
+                                    ended_early(e)
+                                }
                             }
                         });
                     }
@@ -57,138 +236,855 @@ pub async fn tcp_listener(ip_addr: &str) -> anyhow::Result<()> {
                 }
             }
 
-            // Check for 'q' key press to quit
-            res = tokio::task::spawn_blocking(|| {
-                if event::poll(Duration::from_millis(100)).unwrap() {
-                    if let Event::Key(key_event) = event::read().unwrap() {
-                        if key_event.code == KeyCode::Char('q') {
-                            return true;
-                        }
-                    }
+            // Forget about finished clients, but remember their transfers
+            Some(joined) = clients.join_next(), if !clients.is_empty() => {
+                if let Ok(Some(transfer)) = joined {
+                    ended.push(transfer);
                 }
-                false
-            }) => {
-                match res {
-                    Ok(true) => {
-                        let res = "Shutting down server...".to_string();
-                        println!("{}\r", style(res).bold().blue());
-                        break;
-                    }
-                    Ok(false) => {}
-                    Err(e) => log::error!("Error reading key event: {}", e),
+                if ctx.count.is_some_and(|count| ended.len() >= count) {
+                    break Quit::Done;
+                }
+            }
+
+            reason = &mut quit => break reason?,
+        }
+    };
+
+    #[cfg(unix)]
+    notify_systemd(&[sd_notify::NotifyState::Stopping]);
+
+    let res = "Shutting down server...".to_string();
+    status(style(res).bold().blue());
+
+    if let Quit::Signal = quit_reason
+        && !clients.is_empty()
+    {
+        status(format!(
+            "Waiting up to {}s for {} running transfers",
+            SHUTDOWN_GRACE.as_secs(),
+            clients.len()
+        ));
+        let drain = async {
+            while let Some(joined) = clients.join_next().await {
+                if let Ok(Some(transfer)) = joined {
+                    ended.push(transfer);
                 }
             }
+        };
+        if tokio::time::timeout(SHUTDOWN_GRACE, drain).await.is_err() {
+            log::warn!("Aborting {} transfers", clients.len());
         }
+    }
+    // Whatever still runs is aborted here
+    clients.shutdown().await;
 
-        // Sleep to prevent busy waiting
-        sleep(Duration::from_millis(100)).await;
+    if let (Some(board), Some(dashboard)) = (board, dashboard) {
+        board.close();
+        dashboard.await??;
+    } else if !headless {
+        disable_raw_mode()?;
     }
+    Ok(ended)
+}
 
-    disable_raw_mode()?;
-    Ok(())
+/// How long running transfers may take to finish after SIGTERM or SIGINT.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// Why the server stops.
+enum Quit {
+    /// The user pressed 'q'.
+    Key,
+    /// SIGTERM or SIGINT.
+    Signal,
+    /// As many transfers as asked for ended.
+    Done,
 }
 
-use std::fs::File;
-use std::io::Write;
-use std::net::SocketAddr;
-use std::path::PathBuf;
+/// Wait for the user to press 'q'.
+async fn quit_key() -> anyhow::Result<Quit> {
+    loop {
+        let pressed = tokio::task::spawn_blocking(|| -> anyhow::Result<bool> {
+            Ok(event::poll(Duration::from_millis(100))?
+                && matches!(event::read()?, Event::Key(key_event) if key_event.code == KeyCode::Char('q')))
+        })
+        .await??;
+        if pressed {
+            return Ok(Quit::Key);
+        }
+    }
+}
+
+/// Wait for SIGTERM or SIGINT, or Ctrl-C on Windows.
+async fn shutdown_signal() -> anyhow::Result<Quit> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        tokio::select! {
+            _ = terminate.recv() => log::info!("Received SIGTERM"),
+            _ = interrupt.recv() => log::info!("Received SIGINT"),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(Quit::Signal)
+}
 
+/// The longest gap between two `Queued` keepalives.
+const QUEUE_KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// Wait for `work`, e.g. a free transfer slot or the user's answer.
+/// Meanwhile, tell the sender regularly that it is still queued, often
+/// enough that it does not run into `header_timeout`.
+/// # Returns
+/// What `work` came to, or an error once the sender cannot be told.
+async fn keep_alive<T>(
+    stream: &mut TcpStream,
+    header_timeout: Duration,
+    work: impl Future<Output = T>,
+) -> anyhow::Result<T> {
+    tokio::pin!(work);
+
+    let mut ticker = tokio::time::interval((header_timeout / 2).min(QUEUE_KEEPALIVE));
+    // The first tick completes immediately
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            done = &mut work => return Ok(done),
+            _ = ticker.tick() => write_frame_async(stream, &Frame::Queued).await?,
+        }
+    }
+}
+
+/// Move a verified download to its real name.
+/// A directory archive is extracted next to the target first, so the
+/// directory only appears once it is complete.
+/// # Arguments
+/// - `download`: The verified file or archive, under its partial name.
+/// - `target`: Where it belongs.
+/// - `is_dir`: Whether `download` is a directory archive.
+/// - `tag`: Unique to the connection, see [`Output::partial_path`].
+fn put_in_place(download: &Path, target: &Path, is_dir: bool, tag: &str) -> anyhow::Result<()> {
+    if !is_dir {
+        return fs::rename(download, target)
+            .map_err(|e| anyhow::anyhow!("Failed to rename {:?}: {}", download, e));
+    }
+
+    let extracted = Output::partial_path(target, tag);
+    let result = zip_extract(&download.to_path_buf(), &extracted)
+        .map_err(|e| anyhow::anyhow!("Failed to extract {:?}: {}", download, e))
+        .and_then(|_| {
+            log::info!(
+                "Extracted archive {:?} to directory {:?}",
+                download,
+                extracted
+            );
+            replace(&extracted, target, tag)
+                .map_err(|e| anyhow::anyhow!("Failed to move the directory to {:?}: {}", target, e))
+        });
+    // Remove the archive after extraction, and whatever is left on failure
+    if let Err(e) = fs::remove_file(download) {
+        log::error!("Failed to remove archive {:?}: {}", download, e);
+    }
+    if result.is_err() && extracted.exists() {
+        let _ = fs::remove_dir_all(&extracted);
+    }
+    result
+}
+
+/// Rename `from` to `to`, replacing whatever is there, as a received file
+/// replaces an existing one. An existing directory is moved aside first,
+/// since a directory cannot be renamed over another one.
+fn replace(from: &Path, to: &Path, tag: &str) -> std::io::Result<()> {
+    if !to.exists() {
+        return fs::rename(from, to);
+    }
+    let old = to.with_file_name(format!(
+        ".{}.{}.old",
+        to.file_name().unwrap_or_default().to_string_lossy(),
+        tag
+    ));
+    fs::rename(to, &old)?;
+    fs::rename(from, to)?;
+    match old.is_dir() {
+        true => fs::remove_dir_all(&old),
+        false => fs::remove_file(&old),
+    }
+}
+
+/// Ask the user in the dashboard whether to accept a transfer.
+/// Meanwhile, keep the sender waiting with `Queued` keepalives.
+/// # Arguments
+/// - `header_timeout`: How long the sender waits for a keepalive.
+/// - `peer`, `fingerprint`: Who wants to send.
+/// - `name`, `size`: What they want to send.
+/// # Returns
+/// `true` if the user accepted.
+async fn ask_user(
+    stream: &mut TcpStream,
+    header_timeout: Duration,
+    peer: &str,
+    fingerprint: &str,
+    name: &str,
+    size: u64,
+) -> anyhow::Result<bool> {
+    let Some(board) = tui::board() else {
+        return Ok(false);
+    };
+    status(format!(
+        "{} wants to send {}, waiting for an answer",
+        peer, name
+    ));
+    let answer = board.ask(peer, fingerprint, name, size);
+    // A dropped prompt counts as a rejection
+    Ok(keep_alive(stream, header_timeout, answer)
+        .await?
+        .unwrap_or(false))
+}
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Utc;
 use console::style;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::HumanBytes;
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+use rand_core::{OsRng, RngCore};
 use sha2::{Digest, Sha256};
 use tokio::io::AsyncReadExt;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
 use zip_extensions::*;
 
-async fn handle_client(mut stream: TcpStream, addr: SocketAddr) -> anyhow::Result<()> {
-    println!("Client connected: {}\r", addr);
+use config::AcceptPolicy;
+use deliver::addr::is_link_local;
+use deliver::history::{Direction, History, Outcome, Record};
+use deliver::identity::{Identity, new_nonce};
+use deliver::known_peers::{KnownPeers, Trust};
+use deliver::proto::{
+    Failure, Frame, MAX_TEXT_LEN, PROTOCOL_VERSION, SNIPPET_NAME, TransferFailed, read_frame_async,
+    write_frame_async,
+};
+use deliver::timeouts::{StallDetector, TimedOut, Timeouts, within};
+use deliver::uri::DeliverUri;
+use hooks::{HookEvent, Hooks};
+use limits::{Admission, BUSY_RETRY_AFTER, Limiter};
+use output::Output;
+use progress::Transfers;
+#[cfg(unix)]
+use report::notify_systemd;
+use report::{is_headless, status};
+use snippets::Snippets;
 
-    // ANCHOR: receive file name length, name, size, and checksum
-    let mut len_buf = [0u8; 2];
-    stream.read_exact(&mut len_buf).await?;
-    let name_len = u16::from_be_bytes(len_buf) as usize;
+/// Tell a sender that reset the connection and timeouts apart from other
+/// read errors.
+fn classify(e: anyhow::Error) -> TransferFailed {
+    if e.downcast_ref::<TimedOut>().is_some() {
+        return TransferFailed {
+            failure: Failure::TimedOut,
+            reason: e.to_string(),
+        };
+    }
+    let reset = e.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+        )
+    });
+    match reset {
+        true => TransferFailed {
+            failure: Failure::Cancelled,
+            reason: e.to_string(),
+        },
+        false => aborted(e),
+    }
+}
 
-    let mut name_buf = vec![0u8; name_len];
-    stream.read_exact(&mut name_buf).await?;
-    let file_name = String::from_utf8(name_buf).unwrap();
+fn aborted(reason: impl fmt::Display) -> TransferFailed {
+    TransferFailed {
+        failure: Failure::Aborted,
+        reason: reason.to_string(),
+    }
+}
 
-    let mut size_buf = [0u8; 8];
-    stream.read_exact(&mut size_buf).await?;
-    let file_size = u64::from_be_bytes(size_buf);
+/// How a connection that failed before announcing a transfer counts
+/// towards `--count`. One that stalled or broke the protocol does; one that
+/// was just closed again, like a connection attempt the sender gave up on,
+/// does not.
+fn ended_early(e: anyhow::Error) -> Option<Ended> {
+    if is_eof(&e) {
+        return None;
+    }
+    match classify(e).failure {
+        Failure::TimedOut => Some(Ended::TimedOut),
+        Failure::Cancelled => None,
+        _ => Some(Ended::Failed),
+    }
+}
 
-    let mut checksum_buf = [0u8; 32];
-    stream.read_exact(&mut checksum_buf).await?;
-    // ANCHOR_END: receive file name length, name, size, and checksum
+fn is_eof(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+}
 
-    // ANCHOR: display file info
-    let (format_name, file_type) = match file_name.ends_with(".uzip") {
-        true => (file_name.trim_end_matches(".uzip"), "Directory"),
-        false => (file_name.as_str(), "File"),
+/// Hand a snippet that arrived as content on to `snippets`, and remove
+/// the file it was received into.
+/// # Returns
+/// The snippets file if it was saved there.
+fn take_snippet(
+    snippets: &Snippets,
+    download: &Path,
+    peer: &str,
+) -> anyhow::Result<Option<PathBuf>> {
+    let text = fs::read(download)
+        .map_err(|e| anyhow::anyhow!("Failed to read snippet {:?}: {}", download, e))?;
+    if let Err(e) = fs::remove_file(download) {
+        log::error!("Failed to remove snippet {:?}: {}", download, e);
+    }
+    snippets.deliver(peer, &String::from_utf8_lossy(&text))
+}
+
+/// Quarantine or delete a file that arrived broken, as configured.
+/// # Arguments
+/// - `name`: The name it is quarantined under, after a timestamp.
+/// # Returns
+/// What happened to it, for the user. `saved` is set to where it was moved.
+fn dispose(output: &Output, download: &Path, name: &str, saved: &mut Option<PathBuf>) -> String {
+    match output.dispose(download, name) {
+        Ok(Some(quarantined)) => {
+            let moved = format!("Moved to {}", quarantined.display());
+            *saved = Some(quarantined);
+            moved
+        }
+        Ok(None) => "Deleted it".to_string(),
+        Err(e) => {
+            log::error!("{}", e);
+            format!("Left it at {}", download.display())
+        }
+    }
+}
+
+/// Handle one connection, from the handshake to the end of the transfer.
+/// # Arguments
+/// * `admission` - The connection's transfer slot, given back before the
+///   hook runs.
+/// # Returns
+/// How the transfer ended. Errors before the sender announced a transfer
+/// are returned as such; errors after it are logged.
+async fn handle_client(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    ctx: Arc<Context>,
+    admission: Admission,
+) -> anyhow::Result<Ended> {
+    status(format!("Client connected: {}", addr));
+
+    // ANCHOR: handshake with the sender
+    let header_timeout = ctx.timeouts.header();
+    let nonce = new_nonce();
+    write_frame_async(
+        &mut stream,
+        &Frame::Hello {
+            version: PROTOCOL_VERSION,
+            device: ctx.identity.device(),
+            nonce: nonce.clone(),
+        },
+    )
+    .await?;
+
+    let identify = read_frame_async(&mut stream);
+    let (sender, sender_nonce) = match within(
+        header_timeout,
+        "waiting for the sender's handshake",
+        identify,
+    )
+    .await?
+    {
+        Frame::Identify {
+            device,
+            nonce: sender_nonce,
+            signature,
+        } => {
+            device.verify_challenge(&nonce, &signature)?;
+            (device, sender_nonce)
+        }
+        frame => return Err(anyhow::anyhow!("Unexpected frame from sender: {:?}", frame)),
     };
-    println!("Receiving dir: {} ({} bytes)\r", format_name, file_size);
-    // ANCHOR_END: display file info
-
-    // ANCHOR: receive file content with progress bar
-    let mut file = File::create(&file_name)?;
-    let mut received: u64 = 0;
-    let mut buffer = [0; 8192];
-
-    let pb = ProgressBar::new(file_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{msg} {wide_bar} {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .progress_chars("=>-"),
-    );
-    pb.set_message(format!("Receiving {}", format_name));
+    let fingerprint = sender.fingerprint()?;
+    // ANCHOR_END: handshake with the sender
 
-    let mut hasher = Sha256::new();
+    // ANCHOR: receive file name and size
+    let header = read_frame_async(&mut stream);
+    // A short text snippet arrives right here instead of a header
+    let mut inline = None;
+    // The checksum the sender sends after the content
+    let mut checksum = String::new();
+    let (file_name, file_size, is_text, contents) =
+        match within(header_timeout, "waiting for the sender's header", header).await? {
+            Frame::Header {
+                name,
+                size,
+                text,
+                contents,
+            } => (name, size, text, contents),
+            Frame::Text { text } => {
+                let size = text.len() as u64;
+                checksum = hex::encode(Sha256::digest(text.as_bytes()));
+                inline = Some(text);
+                (SNIPPET_NAME.to_string(), size, true, None)
+            }
+            frame => return Err(anyhow::anyhow!("Unexpected frame from sender: {:?}", frame)),
+        };
+    // A directory comes with what its archive holds, to decide by
+    let offered = match contents {
+        Some(contents) => format!(
+            "{} ({} files, {} unpacked)",
+            file_name.trim_end_matches(".uzip"),
+            contents.files,
+            HumanBytes(contents.size)
+        ),
+        None => file_name.clone(),
+    };
+    // ANCHOR_END: receive file name and size
 
-    while received < file_size {
-        let n = stream.read(&mut buffer).await?;
-        if n == 0 {
-            break;
+    let started = Utc::now();
+    let timer = Instant::now();
+    // Where the file is saved, once that is decided
+    let mut saved = None;
+    // Whether `saved` is a truncated file to resume from
+    let mut resumable = false;
+    // The hook to run once the connection is closed
+    let mut hook = None;
+    // How much of the content arrived, once it started arriving
+    let mut transferred = None;
+    let result: anyhow::Result<Outcome> = async {
+        // ANCHOR: decide by the sender's device key
+        let verdict = match KnownPeers::load().get(&fingerprint) {
+            _ if is_text && file_size > MAX_TEXT_LEN as u64 => Err("text is too large"),
+            Some(peer) if peer.trust == Trust::Deny => Err("device is denied"),
+            Some(_) => Ok(()),
+            None if ctx.accept == AcceptPolicy::Known => Err("device is unknown"),
+            None if ctx.accept == AcceptPolicy::Ask => {
+                let answer = ask_user(
+                    &mut stream,
+                    header_timeout,
+                    &sender.name,
+                    &fingerprint,
+                    &offered,
+                    file_size,
+                );
+                match answer.await? {
+                    true => Ok(()),
+                    false => Err("declined by the user"),
+                }
+            }
+            None => {
+                status(format!(
+                    "New device {} ({}), add it to known_peers to allow or deny it",
+                    style(&sender.name).bold(),
+                    fingerprint
+                ));
+                Ok(())
+            }
+        };
+        if let Err(reason) = verdict {
+            status(
+                style(format!(
+                    "Rejected {} from {} ({}): {}",
+                    file_name, sender.name, fingerprint, reason
+                ))
+                .red(),
+            );
+            let reason = reason.to_string();
+            write_frame_async(
+                &mut stream,
+                &Frame::Reject {
+                    reason: reason.clone(),
+                },
+            )
+            .await?;
+            hook = Some(HookEvent::Rejected {
+                reason: reason.clone(),
+            });
+            return Ok(Outcome::Failed {
+                reason: format!("rejected: {}", reason),
+                failure: None,
+            });
         }
-        file.write_all(&buffer[..n])?;
-        hasher.update(&buffer[..n]);
-        received += n as u64;
-        pb.set_position(received);
-    }
 
-    pb.finish_with_message("Receive complete");
-    // ANCHOR_END: receive file content with progress bar
+        write_frame_async(
+            &mut stream,
+            &Frame::Accept {
+                signature: ctx.identity.sign_challenge(&sender_nonce)?,
+            },
+        )
+        .await?;
+        status(format!(
+            "Sender: {} ({})",
+            style(&sender.name).bold(),
+            fingerprint
+        ));
+        // ANCHOR_END: decide by the sender's device key
+
+        // ANCHOR: take a text snippet sent inline
+        if let Some(text) = inline {
+            transferred = Some(file_size);
+            match ctx.snippets.deliver(&sender.name, &text) {
+                Ok(path) => saved = path,
+                Err(e) => {
+                    let failed = aborted(e);
+                    let _ = write_frame_async(
+                        &mut stream,
+                        &Frame::Failed {
+                            failure: failed.failure,
+                            reason: failed.reason.clone(),
+                        },
+                    )
+                    .await;
+                    return Err(failed.into());
+                }
+            }
+            write_frame_async(&mut stream, &Frame::Done).await?;
+            hook = Some(HookEvent::Success);
+            return Ok(Outcome::Success);
+        }
+        // ANCHOR_END: take a text snippet sent inline
+
+        // ANCHOR: display file info
+        let (format_name, file_type) = match (is_text, file_name.ends_with(".uzip")) {
+            (true, _) => (file_name.as_str(), "Text"),
+            (false, true) => (file_name.trim_end_matches(".uzip"), "Directory"),
+            (false, false) => (file_name.as_str(), "File"),
+        };
+        match contents {
+            Some(contents) => status(format!(
+                "Receiving {}: {} ({} bytes, {} files, {} unpacked)",
+                file_type.to_lowercase(),
+                format_name,
+                file_size,
+                contents.files,
+                HumanBytes(contents.size)
+            )),
+            None => status(format!(
+                "Receiving {}: {} ({} bytes)",
+                file_type.to_lowercase(),
+                format_name,
+                file_size
+            )),
+        }
+        // ANCHOR_END: display file info
 
-    // ANCHOR: verify checksum and cleanup
-    let calculated_checksum = hasher.finalize();
-    if calculated_checksum.as_slice() == checksum_buf {
-        let res = format!(
-            "{} {} received successfully. Checksum OK.",
-            file_type, format_name
-        );
-        println!("{}\r", style(res).green());
+        // ANCHOR: receive file content with progress bar
+        // Keeps the temporary files of concurrent transfers of one name apart
+        let tag = format!("{:08x}", OsRng.next_u32());
+        let target = match is_text {
+            // Snippets only pass through the output directory
+            true => ctx.output.dir().join(format!("{}-{}", SNIPPET_NAME, tag)),
+            false => ctx.output.path_for(format_name, &sender.name)?,
+        };
+        // Data goes to a hidden name until it is verified.
+        // Directories arrive as an archive next to where they are extracted,
+        // and are quarantined as that archive.
+        let arrives_as = match file_type {
+            "Directory" => PathBuf::from(format!("{}.uzip", target.display())),
+            _ => target.clone(),
+        };
+        let download = Output::partial_path(&arrives_as, &tag);
+        let kept_name = arrives_as
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let mut file = File::create(&download)?;
+        let mut received: u64 = 0;
+        let mut buffer = [0; 8192];
 
-        if file_type == "Directory" {
-            // Unzip the received .uzip file
-            let archive_file = PathBuf::from(&file_name);
-            let target_dir = PathBuf::from(format_name);
-            zip_extract(&archive_file, &target_dir)?;
+        let bar = ctx.transfers.start(&sender.name, format_name, file_size);
 
-            log::info!(
-                "Extracted archive {} to directory {}",
-                file_name,
-                format_name
+        let mut hasher = Sha256::new();
+        let mut stall = StallDetector::new(&ctx.timeouts);
+        let idle_timeout = ctx.timeouts.idle();
+
+        let transfer: Result<(), TransferFailed> = async {
+            while received < file_size {
+                // Never read past the announced size, so extra bytes show up
+                let want = (file_size - received).min(buffer.len() as u64) as usize;
+                let read = async { Ok(stream.read(&mut buffer[..want]).await?) };
+                let n = tokio::select! {
+                    n = within(idle_timeout, "waiting for data", read) => n.map_err(classify)?,
+                    _ = bar.cancelled() => return Err(aborted("cancelled by the user")),
+                };
+                if n == 0 {
+                    return Err(TransferFailed {
+                        failure: Failure::Truncated,
+                        reason: format!(
+                            "the sender stopped after {} of {} bytes",
+                            received, file_size
+                        ),
+                    });
+                }
+                stall.record(n).map_err(classify)?;
+                file.write_all(&buffer[..n]).map_err(aborted)?;
+                hasher.update(&buffer[..n]);
+                received += n as u64;
+                bar.inc(n as u64);
+            }
+
+            // The checksum follows the content, anything else there is more
+            // than the sender announced
+            let trailer = read_frame_async(&mut stream);
+            match within(idle_timeout, "waiting for the checksum", trailer).await {
+                Ok(Frame::Trailer { checksum: sent }) => {
+                    checksum = sent;
+                    Ok(())
+                }
+                Err(e) if is_eof(&e) => Err(TransferFailed {
+                    failure: Failure::Truncated,
+                    reason: "the sender stopped before the checksum".to_string(),
+                }),
+                Err(e) if e.is::<std::io::Error>() || e.is::<TimedOut>() => Err(classify(e)),
+                _ => Err(TransferFailed {
+                    failure: Failure::Oversized,
+                    reason: format!(
+                        "the sender sent more than the {} bytes it announced",
+                        file_size
+                    ),
+                }),
+            }
+        }
+        .await;
+        transferred = Some(received);
+        drop(file);
+
+        if let Err(failed) = transfer {
+            let kept = match failed.failure {
+                // What arrived is intact, so it can be resumed from
+                Failure::Truncated if !is_text => {
+                    let kept =
+                        ctx.output
+                            .keep_for_resume(&download, &kept_name, &fingerprint, file_size);
+                    match kept {
+                        Ok(kept) => {
+                            let note = format!(
+                                "Kept {} bytes at {} to resume from",
+                                received,
+                                kept.display()
+                            );
+                            saved = Some(kept);
+                            resumable = true;
+                            note
+                        }
+                        Err(e) => {
+                            log::error!("{}", e);
+                            format!("Left it at {}", download.display())
+                        }
+                    }
+                }
+                Failure::Oversized => dispose(&ctx.output, &download, &kept_name, &mut saved),
+                // Do not leave a partial file behind
+                _ => {
+                    if let Err(e) = fs::remove_file(&download) {
+                        log::error!("Failed to remove partial file {:?}: {}", download, e);
+                    }
+                    "Deleted it".to_string()
+                }
+            };
+
+            let summary = format!(
+                "{} {} from {} failed: {}. {}",
+                file_type, format_name, sender.name, failed, kept
             );
+            bar.finish(false, style(summary).red());
+            let _ = write_frame_async(
+                &mut stream,
+                &Frame::Failed {
+                    failure: failed.failure,
+                    reason: failed.reason.clone(),
+                },
+            )
+            .await;
+            return Err(failed.into());
+        }
+
+        // ANCHOR_END: receive file content with progress bar
+
+        // ANCHOR: verify checksum and cleanup
+        let calculated_checksum = hasher.finalize();
+        if hex::encode(calculated_checksum) == checksum {
+            // The sender only hears of success once the content is in place
+            let mut placing = {
+                let snippets = ctx.snippets.clone();
+                let (download, target, tag) = (download.clone(), target.clone(), tag.clone());
+                let peer = sender.name.clone();
+                let is_dir = file_type == "Directory";
+                tokio::task::spawn_blocking(move || match is_text {
+                    true => take_snippet(&snippets, &download, &peer),
+                    false => put_in_place(&download, &target, is_dir, &tag).map(|()| Some(target)),
+                })
+            };
+            let placed = match keep_alive(&mut stream, header_timeout, &mut placing).await {
+                Ok(placed) => placed,
+                // The saving goes on even if the sender is gone
+                Err(e) => {
+                    log::debug!("Failed to keep {} waiting: {}", addr, e);
+                    placing.await
+                }
+            };
+            let placed = match placed {
+                Ok(placed) => placed,
+                Err(e) => Err(e.into()),
+            };
+            let placed = match placed {
+                Ok(placed) => placed,
+                Err(e) => {
+                    // A file that could not be renamed is still there
+                    let kept = match download.exists() {
+                        true => format!(
+                            " {}",
+                            dispose(&ctx.output, &download, &kept_name, &mut saved)
+                        ),
+                        false => String::new(),
+                    };
+                    let summary = format!(
+                        "{} {} from {} could not be saved: {}.{}",
+                        file_type, format_name, sender.name, e, kept
+                    );
+                    bar.finish(false, style(summary).red());
+                    let failed = aborted(format!("could not save it: {}", e));
+                    let _ = write_frame_async(
+                        &mut stream,
+                        &Frame::Failed {
+                            failure: failed.failure,
+                            reason: failed.reason.clone(),
+                        },
+                    )
+                    .await;
+                    return Err(failed.into());
+                }
+            };
+            if let Err(e) = write_frame_async(&mut stream, &Frame::Done).await {
+                log::debug!("Failed to tell {} the transfer succeeded: {}", addr, e);
+            }
 
-            // Remove the .uzip file after extraction
-            std::fs::remove_file(&file_name)?;
+            let res = match &placed {
+                Some(path) => format!(
+                    "{} {} from {} received successfully. Checksum OK. Saved to {}",
+                    file_type,
+                    format_name,
+                    sender.name,
+                    path.display()
+                ),
+                None => format!(
+                    "{} {} from {} received successfully. Checksum OK",
+                    file_type, format_name, sender.name
+                ),
+            };
+            bar.finish(true, style(res).green());
+            saved = placed;
+            hook = Some(HookEvent::Success);
+            Ok(Outcome::Success)
+        } else {
+            let reason = "checksum mismatch".to_string();
+            let _ = write_frame_async(
+                &mut stream,
+                &Frame::Failed {
+                    failure: Failure::Corrupt,
+                    reason: reason.clone(),
+                },
+            )
+            .await;
+            let disposed = dispose(&ctx.output, &download, &kept_name, &mut saved);
+            let res = format!(
+                "{} {} from {} received, but checksum mismatch! {}",
+                file_type, format_name, sender.name, disposed
+            );
+            bar.finish(false, style(res).red());
+            hook = Some(HookEvent::ChecksumMismatch);
+            Ok(Outcome::Failed {
+                reason,
+                failure: Some(Failure::Corrupt),
+            })
         }
-    } else {
-        println!(
-            "{} {} received, but checksum mismatch!\r",
-            file_type, format_name
-        );
+        // ANCHOR_END: verify checksum and cleanup
     }
-    // ANCHOR_END: verify checksum and cleanup
+    .await;
 
-    Ok(())
+    // ANCHOR: record the transfer in the history
+    let name = file_name.trim_end_matches(".uzip");
+    History::record(&Record {
+        time: started,
+        direction: Direction::Received,
+        peer: sender.name.clone(),
+        fingerprint: Some(fingerprint.clone()),
+        addr: addr.to_string(),
+        name: name.to_string(),
+        path: saved
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+        size: file_size,
+        transferred,
+        resumable,
+        sha256: checksum.clone(),
+        duration_secs: timer.elapsed().as_secs_f64(),
+        outcome: match &result {
+            Ok(outcome) => outcome.clone(),
+            Err(e) => Outcome::from_error(e),
+        },
+    });
+    // ANCHOR_END: record the transfer in the history
+
+    let ended = match (&result, &hook) {
+        (Ok(Outcome::Success), _) => Ended::Success,
+        (_, Some(HookEvent::Rejected { .. })) => Ended::Rejected,
+        (_, Some(HookEvent::ChecksumMismatch)) => Ended::ChecksumMismatch,
+        (Err(e), _) if timed_out(e) => Ended::TimedOut,
+        _ => Ended::Failed,
+    };
+    if let Err(e) = &result {
+        log::error!("Error handling client {}: {}", addr, e);
+    }
+
+    // ANCHOR: run the hook for how the transfer ended
+    // A slow hook must not keep other senders from a transfer slot
+    drop(admission);
+    if let Some(event) = hook {
+        // The sender has nothing left to wait for
+        drop(stream);
+        let kind = match (is_text, file_name.ends_with(".uzip")) {
+            (true, _) => "text",
+            (false, true) => "directory",
+            (false, false) => "file",
+        };
+        let transfer = hooks::Transfer {
+            path: saved.as_deref(),
+            peer: &sender.name,
+            fingerprint: &fingerprint,
+            addr: &addr.to_string(),
+            name,
+            size: file_size,
+            sha256: &checksum,
+            kind,
+        };
+        ctx.hooks.run(&event, &transfer).await;
+    }
+    // ANCHOR_END: run the hook for how the transfer ended
+
+    Ok(ended)
+}
+
+fn timed_out(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<TimedOut>().is_some()
+        || e.downcast_ref::<TransferFailed>()
+            .is_some_and(|failed| failed.failure == Failure::TimedOut)
 }
@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use deliver::pkg_info::PkgInfo;
+use deliver::timeouts::Timeouts;
+
+use super::hooks::Hooks;
+use super::limits::Limits;
+use super::listen::PortFallback;
+use super::output::FailedFiles;
+use super::snippets::SnippetMode;
+
+/// Which senders are accepted.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AcceptPolicy {
+    /// Every device that is not denied
+    #[default]
+    All,
+    /// Only devices marked `allow` in the known peers list
+    Known,
+    /// Ask in the dashboard for devices not marked `allow`
+    Ask,
+}
+
+/// The receiver's settings, from `receiver.toml` in the config directory.
+/// Environment variables and command line options override them.
+/// # Example
+/// ```toml
+/// port = 9000
+/// bind = ["192.168.1.20"]
+/// out_dir = "/srv/inbox"
+/// accept = "known"
+///
+/// [limits]
+/// max_transfers = 8
+///
+/// [hooks]
+/// on_success = "photo-import \"$DELIVER_PATH\""
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReceiverCfg {
+    pub port: u16,
+    /// Local addresses to listen on, all interfaces if empty.
+    pub bind: Vec<String>,
+    pub port_fallback: PortFallback,
+    /// Where received files are saved, the working directory if unset.
+    pub out_dir: Option<PathBuf>,
+    /// The path of each received file below `out_dir`.
+    pub file_template: String,
+    /// What happens to files that fail the checksum.
+    pub failed_files: FailedFiles,
+    /// Where failed files are moved to, `.quarantine` in `out_dir` if unset.
+    pub quarantine_dir: Option<PathBuf>,
+    /// What happens to text snippets.
+    pub snippets: SnippetMode,
+    /// Where text snippets are saved, `snippets.txt` in `out_dir` if unset.
+    pub snippets_file: Option<PathBuf>,
+    pub accept: AcceptPolicy,
+    /// Answer LAN discovery probes and publish an mDNS service.
+    pub announce: bool,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    pub hooks: Hooks,
+}
+
+impl Default for ReceiverCfg {
+    fn default() -> Self {
+        Self {
+            port: 9000,
+            bind: Vec::new(),
+            port_fallback: PortFallback::Off,
+            out_dir: None,
+            file_template: "{name}".to_string(),
+            failed_files: FailedFiles::Quarantine,
+            quarantine_dir: None,
+            snippets: SnippetMode::Print,
+            snippets_file: None,
+            accept: AcceptPolicy::All,
+            announce: true,
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+impl ReceiverCfg {
+    /// Load `receiver.toml`, or the defaults if there is none.
+    /// Unlike the sender, a broken file is an error rather than ignored,
+    /// so a service does not silently start with other settings.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut path = PkgInfo::new().get_config_dir();
+        path.push("receiver.toml");
+
+        log::debug!("Loading config from {:?}", path);
+
+        match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data)
+                .map_err(|e| anyhow::anyhow!("Invalid config file {}:\n{}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::debug!("Config file not found, using default config");
+
+                Ok(ReceiverCfg::default())
+            }
+            Err(e) => Err(anyhow::anyhow!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+
+    /// Check values that parse fine but make no sense.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let at_least_one = [
+            ("max_transfers", self.limits.max_transfers),
+            ("max_per_ip", self.limits.max_per_ip),
+        ];
+        for (name, value) in at_least_one {
+            if value == 0 {
+                return Err(anyhow::anyhow!("{} must be at least 1", name));
+            }
+        }
+        if self.timeouts.header == 0 || self.timeouts.idle == 0 {
+            return Err(anyhow::anyhow!(
+                "The header and idle timeouts must be at least 1 second"
+            ));
+        }
+        if self.hooks.timeout == 0 {
+            return Err(anyhow::anyhow!(
+                "The hook timeout must be at least 1 second"
+            ));
+        }
+        Ok(())
+    }
+}
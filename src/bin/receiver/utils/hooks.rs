@@ -0,0 +1,179 @@
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+
+use super::report::status;
+
+/// Commands run after a transfer, through the shell.
+/// They get the details of the transfer in `DELIVER_*` environment
+/// variables, see [`Transfer`].
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Run after a file was saved and its checksum matched.
+    pub on_success: Option<String>,
+    /// Run after a file arrived with the wrong checksum.
+    pub on_checksum_mismatch: Option<String>,
+    /// Run after a transfer was rejected.
+    pub on_reject: Option<String>,
+    /// Seconds a hook may run before it is killed.
+    pub timeout: u64,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            on_success: None,
+            on_checksum_mismatch: None,
+            on_reject: None,
+            timeout: 60,
+        }
+    }
+}
+
+/// What a hook is run for, `DELIVER_EVENT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookEvent {
+    Success,
+    ChecksumMismatch,
+    /// The reason is passed in `DELIVER_REASON`.
+    Rejected {
+        reason: String,
+    },
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Success => "success",
+            HookEvent::ChecksumMismatch => "checksum_mismatch",
+            HookEvent::Rejected { .. } => "rejected",
+        }
+    }
+}
+
+/// The transfer a hook is run for.
+pub struct Transfer<'a> {
+    /// Where the file was saved or quarantined, `DELIVER_PATH`.
+    /// Unset for rejections and deleted files.
+    pub path: Option<&'a Path>,
+    /// The sender's device name, `DELIVER_PEER`.
+    pub peer: &'a str,
+    /// `DELIVER_FINGERPRINT`
+    pub fingerprint: &'a str,
+    /// The sender's address, `DELIVER_PEER_ADDR`.
+    pub addr: &'a str,
+    /// The file or directory name, `DELIVER_NAME`.
+    pub name: &'a str,
+    /// `DELIVER_SIZE`
+    pub size: u64,
+    /// The checksum sent by the sender, `DELIVER_SHA256`.
+    /// Empty for rejections, which end before the content.
+    pub sha256: &'a str,
+    /// "file", "directory" or "text" for a snippet, `DELIVER_TYPE`.
+    pub kind: &'a str,
+}
+
+impl Hooks {
+    /// Run the hook configured for `event`, if any, and wait for it.
+    /// Its output is logged line by line; failures are reported but do not
+    /// affect the transfer.
+    pub async fn run(&self, event: &HookEvent, transfer: &Transfer<'_>) {
+        let hook = match event {
+            HookEvent::Success => &self.on_success,
+            HookEvent::ChecksumMismatch => &self.on_checksum_mismatch,
+            HookEvent::Rejected { .. } => &self.on_reject,
+        };
+        let Some(command) = hook else {
+            return;
+        };
+
+        log::info!(target: "receiver::hook", "Running {} hook: {}", event.name(), command);
+        let timeout = Duration::from_secs(self.timeout);
+        match run_command(command, event, transfer, timeout).await {
+            Ok(exit) if exit.success() => {
+                log::info!(target: "receiver::hook", "The {} hook finished", event.name())
+            }
+            Ok(exit) => status(format!("The {} hook failed: {}", event.name(), exit)),
+            Err(e) => status(format!("The {} hook failed: {}", event.name(), e)),
+        }
+    }
+}
+
+/// Run `command` through the shell and log its output.
+/// It is killed if it runs longer than `timeout`.
+async fn run_command(
+    command: &str,
+    event: &HookEvent,
+    transfer: &Transfer<'_>,
+    timeout: Duration,
+) -> anyhow::Result<ExitStatus> {
+    let mut shell = match cfg!(windows) {
+        true => {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        }
+        false => {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        }
+    };
+    shell
+        .arg(command)
+        .env("DELIVER_EVENT", event.name())
+        .env("DELIVER_PEER", transfer.peer)
+        .env("DELIVER_FINGERPRINT", transfer.fingerprint)
+        .env("DELIVER_PEER_ADDR", transfer.addr)
+        .env("DELIVER_NAME", transfer.name)
+        .env("DELIVER_SIZE", transfer.size.to_string())
+        .env("DELIVER_SHA256", transfer.sha256)
+        .env("DELIVER_TYPE", transfer.kind)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(path) = transfer.path {
+        shell.env("DELIVER_PATH", path);
+    }
+    if let HookEvent::Rejected { reason } = event {
+        shell.env("DELIVER_REASON", reason);
+    }
+
+    let mut child = shell
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to start the hook: {}", e))?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let run = async {
+        tokio::join!(log_output(stdout, event), log_output(stderr, event));
+        child.wait().await
+    };
+    match tokio::time::timeout(timeout, run).await {
+        Ok(exit) => Ok(exit?),
+        Err(_) => {
+            child.kill().await?;
+            Err(anyhow::anyhow!(
+                "killed after {} seconds",
+                timeout.as_secs()
+            ))
+        }
+    }
+}
+
+/// Log each line a hook writes to stdout or stderr.
+async fn log_output(output: Option<impl AsyncRead + Unpin>, event: &HookEvent) {
+    let Some(output) = output else {
+        return;
+    };
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        log::info!(target: "receiver::hook", "[{}] {}", event.name(), line);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Seconds a sender is asked to wait before retrying when we are busy.
+pub const BUSY_RETRY_AFTER: u64 = 2;
+
+/// Bounds on the work the receiver takes on at once.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Transfers running at the same time.
+    pub max_transfers: usize,
+    /// Transfers running or queued from a single source IP.
+    pub max_per_ip: usize,
+    /// Connections waiting for a free transfer slot.
+    pub queue_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_transfers: 4,
+            max_per_ip: 2,
+            queue_size: 16,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    per_ip: HashMap<IpAddr, usize>,
+    queued: usize,
+}
+
+/// Admission control for incoming connections.
+/// A connection is either admitted, possibly waiting in the queue until a
+/// transfer slot frees up, or turned away so the sender can retry later.
+pub struct Limiter {
+    limits: Limits,
+    transfers: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Arc<Self> {
+        Arc::new(Self {
+            limits,
+            transfers: Arc::new(Semaphore::new(limits.max_transfers)),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Admit a connection from `ip`, or `None` if it is over a limit.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Option<Admission> {
+        let mut state = self.state.lock().unwrap();
+
+        let from_ip = state.per_ip.get(&ip).copied().unwrap_or(0);
+        if from_ip >= self.limits.max_per_ip {
+            log::debug!("{} already has {} connections", ip, from_ip);
+            return None;
+        }
+
+        if self.transfers.available_permits() == 0 && state.queued >= self.limits.queue_size {
+            log::debug!("Queue is full ({} waiting)", state.queued);
+            return None;
+        }
+
+        *state.per_ip.entry(ip).or_default() += 1;
+        state.queued += 1;
+
+        Some(Admission {
+            limiter: self.clone(),
+            ip,
+            permit: None,
+        })
+    }
+}
+
+/// An admitted connection. It gives its slot back when dropped.
+pub struct Admission {
+    limiter: Arc<Limiter>,
+    ip: IpAddr,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Admission {
+    /// Wait in the queue until a transfer slot is free.
+    pub async fn wait(&mut self) {
+        let permit = self
+            .limiter
+            .transfers
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("transfer semaphore is never closed");
+
+        self.limiter.state.lock().unwrap().queued -= 1;
+        self.permit = Some(permit);
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+
+        if self.permit.is_none() {
+            state.queued -= 1;
+        }
+
+        if let Some(count) = state.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                state.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
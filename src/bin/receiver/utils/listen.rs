@@ -0,0 +1,107 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+
+/// How many ports `--port-fallback` tries before giving up.
+const MAX_PORT_ATTEMPTS: usize = 32;
+
+/// What to do when the requested port is already in use.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PortFallback {
+    /// Exit with an error
+    #[default]
+    Off,
+    /// Try the following ports, one after another
+    Next,
+    /// Let the operating system pick a free port
+    Any,
+}
+
+/// Bind a listener for each of `binds`, all on the same port.
+/// Without `binds`, listen dual-stack on all interfaces, or on all IPv4
+/// interfaces when IPv6 is disabled.
+/// # Arguments
+/// - `binds`: The local addresses to listen on; their ports are ignored.
+/// - `port`: The port to listen on.
+/// - `fallback`: What to do when `port` is already in use.
+/// # Returns
+/// The listeners, which all share the port that was chosen.
+pub fn bind_listeners(
+    binds: &[SocketAddr],
+    port: u16,
+    fallback: PortFallback,
+) -> anyhow::Result<Vec<TcpListener>> {
+    let mut port = port;
+    for _ in 0..MAX_PORT_ATTEMPTS {
+        match bind_all(binds, port) {
+            Ok(listeners) => return Ok(listeners),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && fallback != PortFallback::Off => {
+                log::debug!("Port {} is in use: {}", port, e);
+                port = match fallback {
+                    PortFallback::Next => port
+                        .checked_add(1)
+                        .ok_or_else(|| anyhow::anyhow!("No free port above {}", port))?,
+                    _ => 0,
+                };
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                return Err(anyhow::anyhow!(
+                    "Port {} is already in use, pick another with --port or use --port-fallback",
+                    port
+                ));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(anyhow::anyhow!(
+        "No free port found after {} attempts",
+        MAX_PORT_ATTEMPTS
+    ))
+}
+
+/// Bind every address on `port`. With port 0, the first listener gets a
+/// port from the operating system and the others follow it.
+fn bind_all(binds: &[SocketAddr], port: u16) -> io::Result<Vec<TcpListener>> {
+    if binds.is_empty() {
+        let any = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port);
+        return match bind_listener(any, true) {
+            Err(e) if e.kind() != io::ErrorKind::AddrInUse => {
+                // IPv6 may be disabled on this host
+                log::warn!("Failed to listen on {}: {}, falling back to IPv4", any, e);
+                let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+                Ok(vec![bind_listener(any, false)?])
+            }
+            listener => Ok(vec![listener?]),
+        };
+    }
+
+    let mut port = port;
+    let mut listeners = Vec::new();
+    for bind in binds {
+        let mut addr = *bind;
+        addr.set_port(port);
+        let listener = bind_listener(addr, false)?;
+        port = listener.local_addr()?.port();
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Bind a TCP listener. With `dual_stack`, the IPv6 wildcard address
+/// serves IPv4 clients on the same socket.
+fn bind_listener(addr: SocketAddr, dual_stack: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
@@ -0,0 +1,329 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::Local;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Where files that arrived cut short are kept, in the output directory.
+const RESUME_DIR: &str = ".resume";
+
+/// The placeholders a file name template may use.
+const PLACEHOLDERS: &[&str] = &["name", "stem", "ext", "peer", "date", "time"];
+
+/// What happens to a file that failed verification.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FailedFiles {
+    /// Move it to the quarantine directory
+    #[default]
+    Quarantine,
+    /// Delete it
+    Delete,
+}
+
+/// Where received files are saved.
+/// # Example
+/// ```
+/// let output = Output::new(PathBuf::from("inbox"), "{date}/{peer}/{name}")?;
+/// // inbox/2024-05-01/laptop/notes.md
+/// let path = output.path_for("notes.md", "laptop")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Output {
+    dir: PathBuf,
+    template: String,
+    failed_files: FailedFiles,
+    quarantine_dir: PathBuf,
+}
+
+impl Output {
+    /// # Arguments
+    /// - `dir`: The directory everything is saved under, created if missing.
+    /// - `template`: The path of each file below `dir`, with placeholders
+    ///   `{name}`, `{stem}`, `{ext}`, `{peer}`, `{date}` and `{time}`.
+    pub fn new(dir: PathBuf, template: &str) -> anyhow::Result<Self> {
+        check_template(template)?;
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create output directory {:?}: {}", dir, e))?;
+        Ok(Self {
+            quarantine_dir: dir.join(".quarantine"),
+            dir,
+            template: template.to_string(),
+            failed_files: FailedFiles::Quarantine,
+        })
+    }
+
+    /// Set what happens to files that fail verification.
+    /// # Arguments
+    /// - `quarantine_dir`: Where they are moved to, `.quarantine` in the
+    ///   output directory if `None`.
+    pub fn with_failed_files(
+        mut self,
+        policy: FailedFiles,
+        quarantine_dir: Option<PathBuf>,
+    ) -> Self {
+        self.failed_files = policy;
+        if let Some(dir) = quarantine_dir {
+            self.quarantine_dir = dir;
+        }
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The path to save a file at, with its parent directories created.
+    /// # Arguments
+    /// - `name`: The file or directory name the sender gave.
+    /// - `peer`: The sender's device name.
+    pub fn path_for(&self, name: &str, peer: &str) -> anyhow::Result<PathBuf> {
+        // Only the last component, so a sender cannot write outside `dir`
+        let name = Path::new(name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid file name from sender: {:?}", name))?;
+        let path = Path::new(name);
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+        let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+
+        let now = Local::now();
+        let mut template = self.template.clone();
+        if ext.is_empty() {
+            // "{stem}.{ext}" of a name without extension is just "{stem}"
+            template = template.replace(".{ext}", "");
+        }
+        // One pass, so placeholders in the values are left alone
+        let mut relative = String::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            relative.push_str(&rest[..start]);
+            let value = match &rest[start + 1..start + len] {
+                "name" => name.to_string(),
+                "stem" => stem.to_string(),
+                "ext" => ext.to_string(),
+                "peer" => sanitize(peer),
+                "date" => now.format("%Y-%m-%d").to_string(),
+                "time" => now.format("%H-%M-%S").to_string(),
+                _ => rest[start..start + len + 1].to_string(),
+            };
+            relative.push_str(&value);
+            rest = &rest[start + len + 1..];
+        }
+        relative.push_str(rest);
+
+        let path = self.dir.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+
+    /// The hidden name data for `path` is written to until it is verified.
+    /// It is in the same directory, so it can be renamed into place atomically.
+    /// # Arguments
+    /// - `tag`: Unique to the connection, so concurrent transfers of the
+    ///   same name do not write to the same file.
+    /// # Example
+    /// ```
+    /// // inbox/.notes.md.1f2e3d4c.part
+    /// let partial = Output::partial_path(Path::new("inbox/notes.md"), "1f2e3d4c");
+    /// ```
+    pub fn partial_path(path: &Path, tag: &str) -> PathBuf {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        path.with_file_name(format!(".{}.{}.part", name, tag))
+    }
+
+    /// Get a file that failed verification out of the way, by moving it to
+    /// the quarantine directory or deleting it.
+    /// # Arguments
+    /// - `path`: The file to get rid of.
+    /// - `name`: The name it is quarantined under, after a timestamp.
+    /// # Returns
+    /// Where it was quarantined, or `None` if it was deleted.
+    pub fn dispose(&self, path: &Path, name: &str) -> anyhow::Result<Option<PathBuf>> {
+        if self.failed_files == FailedFiles::Delete {
+            fs::remove_file(path)?;
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.quarantine_dir).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to create quarantine directory {:?}: {}",
+                self.quarantine_dir,
+                e
+            )
+        })?;
+        let stamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let quarantined = self.quarantine_dir.join(format!("{}_{}", stamp, name));
+        // Renaming fails across file systems, copy then
+        fs::rename(path, &quarantined)
+            .or_else(|_| fs::copy(path, &quarantined).and_then(|_| fs::remove_file(path)))
+            .map_err(|e| anyhow::anyhow!("Failed to quarantine {:?}: {}", path, e))?;
+        Ok(Some(quarantined))
+    }
+
+    /// Keep a file that arrived cut short to resume from, in `.resume` in
+    /// the output directory. It is keyed by the sender, the name and the
+    /// announced size, so a later attempt of the same file replaces it.
+    /// # Arguments
+    /// - `path`: The partial file.
+    /// - `name`: The name of the file or archive that arrived.
+    /// - `fingerprint`: The sender's device fingerprint.
+    /// - `size`: The size the sender announced.
+    /// # Returns
+    /// Where it was kept.
+    /// # Example
+    /// ```
+    /// // inbox/.resume/e296467dfda16ebd-1024-notes.md
+    /// let kept = output.keep_for_resume(&partial, "notes.md", "SHA256:e296467dfda16ebd...", 1024)?;
+    /// ```
+    pub fn keep_for_resume(
+        &self,
+        path: &Path,
+        name: &str,
+        fingerprint: &str,
+        size: u64,
+    ) -> anyhow::Result<PathBuf> {
+        let dir = self.dir.join(RESUME_DIR);
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+        let fingerprint = fingerprint.trim_start_matches("SHA256:");
+        let kept = dir.join(format!(
+            "{}-{}-{}",
+            &fingerprint[..fingerprint.len().min(16)],
+            size,
+            name
+        ));
+        fs::rename(path, &kept)
+            .map_err(|e| anyhow::anyhow!("Failed to keep {:?} to resume from: {}", path, e))?;
+        Ok(kept)
+    }
+}
+
+/// Reject templates with unknown placeholders or that could leave the
+/// output directory.
+fn check_template(template: &str) -> anyhow::Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err(anyhow::anyhow!(
+                "Unclosed placeholder in template {}",
+                template
+            ));
+        };
+        let placeholder = &rest[start + 1..start + len];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(anyhow::anyhow!(
+                "Unknown placeholder {{{}}} in template {}, expected one of {{{}}}",
+                placeholder,
+                template,
+                PLACEHOLDERS.join("}, {")
+            ));
+        }
+        rest = &rest[start + len + 1..];
+    }
+
+    let escapes = Path::new(template)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if escapes || template.ends_with('/') {
+        return Err(anyhow::anyhow!(
+            "The template {} must be a relative file path without '..'",
+            template
+        ));
+    }
+    Ok(())
+}
+
+/// Make a device name safe to use as a directory name.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') => c,
+            _ => '_',
+        })
+        .collect();
+    // Leading dots would hide it, or with spaces between them be "." or ".."
+    match name
+        .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_end()
+    {
+        "" => "unknown".to_string(),
+        name => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_template_accepts_known_placeholders() {
+        for template in [
+            "{name}",
+            "{peer}/{date}/{name}",
+            "./{stem}-{time}.{ext}",
+            "inbox/fixed.txt",
+        ] {
+            assert!(check_template(template).is_ok(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn check_template_rejects_bad_templates() {
+        for template in [
+            "{nmae}",
+            "{}",
+            "{name",
+            "{peer}/{Name}",
+            "../{name}",
+            "{peer}/../../{name}",
+            "/tmp/{name}",
+            "{peer}/",
+        ] {
+            assert!(check_template(template).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn path_for_leaves_placeholders_in_names_alone() {
+        let dir = std::env::temp_dir().join(format!("deliver-output-{}", std::process::id()));
+        let output = Output::new(dir.clone(), "{peer}/{stem}-{date}.{ext}").unwrap();
+        let date = Local::now().format("%Y-%m-%d").to_string();
+        assert_eq!(
+            output.path_for("{peer}.txt", "alice").unwrap(),
+            dir.join("alice").join(format!("{{peer}}-{}.txt", date))
+        );
+        assert_eq!(
+            output.path_for("x-{date}", "{name}").unwrap(),
+            dir.join("_name_").join(format!("x-{{date}}-{}", date))
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sanitize_keeps_safe_names() {
+        assert_eq!(sanitize("alice-laptop_2"), "alice-laptop_2");
+        assert_eq!(sanitize("Zoë's Mac.local"), "Zoë_s Mac.local");
+        assert_eq!(sanitize("  bob  "), "bob");
+    }
+
+    #[test]
+    fn sanitize_cannot_leave_the_directory() {
+        assert_eq!(sanitize("../etc"), "_etc");
+        assert_eq!(sanitize("a/b\\c"), "a_b_c");
+        assert_eq!(sanitize(".hidden"), "hidden");
+        assert_eq!(sanitize(".."), "unknown");
+        assert_eq!(sanitize(". ."), "unknown");
+        assert_eq!(sanitize(""), "unknown");
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use console::style;
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tokio::sync::Notify;
+
+use super::report::{progress, status};
+use super::tui;
+
+/// The progress view of all running transfers: one labeled bar per
+/// transfer and a total line below them. Finished transfers leave a
+/// single summary line behind. With the dashboard, transfers are shown
+/// there instead.
+pub struct Transfers {
+    total: ProgressBar,
+    counts: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    active: usize,
+    done: usize,
+    failed: usize,
+}
+
+impl Transfers {
+    pub fn new() -> Arc<Self> {
+        // Drawn only once it is added to the view
+        let total = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden());
+        total.set_style(
+            ProgressStyle::default_bar()
+                .template("{msg:.bold} {wide_bar} {bytes}/{total_bytes} {binary_bytes_per_sec}")
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+        Arc::new(Self {
+            total,
+            counts: Mutex::new(Counts::default()),
+        })
+    }
+
+    /// Add a bar for a transfer that is about to start.
+    /// # Arguments
+    /// - `peer`: The sender's device name.
+    /// - `name`: The file or directory being received.
+    /// - `size`: The number of bytes to receive.
+    pub fn start(self: &Arc<Self>, peer: &str, name: &str, size: u64) -> TransferBar {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.active == 0 {
+            self.total.reset();
+            self.total.set_length(0);
+            progress().add(self.total.clone());
+        }
+        counts.active += 1;
+        self.total.inc_length(size);
+        self.update_total(&counts);
+
+        let bar = progress().insert_before(&self.total, ProgressBar::new(size));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{prefix:.bold} {msg} {wide_bar} {bytes}/{total_bytes} {binary_bytes_per_sec} ({eta})")
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+        bar.set_prefix(peer.to_string());
+        bar.set_message(name.to_string());
+
+        let cancel = Arc::new(Notify::new());
+        let board_id =
+            tui::board().map(|board| board.add_transfer(peer, name, size, cancel.clone()));
+
+        TransferBar {
+            transfers: self.clone(),
+            bar,
+            cancel,
+            board_id,
+            finished: false,
+        }
+    }
+
+    fn update_total(&self, counts: &Counts) {
+        self.total.set_message(format!(
+            "Total: {} running, {} done, {} failed",
+            counts.active, counts.done, counts.failed
+        ));
+    }
+
+    /// Take a transfer off the view.
+    fn end(&self, bar: &ProgressBar, board_id: Option<u64>, success: bool) {
+        if let (Some(board), Some(id)) = (tui::board(), board_id) {
+            board.remove_transfer(id);
+        }
+
+        let mut counts = self.counts.lock().unwrap();
+        counts.active -= 1;
+        match success {
+            true => counts.done += 1,
+            false => counts.failed += 1,
+        }
+        // What was not received is no longer expected
+        let missing = bar.length().unwrap_or(0).saturating_sub(bar.position());
+        self.total.dec_length(missing);
+        self.update_total(&counts);
+
+        progress().remove(bar);
+        if counts.active == 0 {
+            progress().remove(&self.total);
+        }
+    }
+}
+
+/// The bar of one running transfer. It is taken off the view when the
+/// transfer ends, or when it is dropped on an error path.
+pub struct TransferBar {
+    transfers: Arc<Transfers>,
+    bar: ProgressBar,
+    /// Notified when the user cancels the transfer in the dashboard.
+    cancel: Arc<Notify>,
+    board_id: Option<u64>,
+    finished: bool,
+}
+
+impl TransferBar {
+    /// Count `n` more bytes received.
+    pub fn inc(&self, n: u64) {
+        self.bar.inc(n);
+        self.transfers.total.inc(n);
+        if let (Some(board), Some(id)) = (tui::board(), self.board_id) {
+            board.advance(id, n);
+        }
+    }
+
+    /// Wait until the user cancels the transfer.
+    pub async fn cancelled(&self) {
+        self.cancel.notified().await
+    }
+
+    /// Replace the bar with a summary line.
+    /// # Arguments
+    /// - `success`: Whether the transfer counts as done or failed.
+    /// - `summary`: What happened, followed by the amount, time and speed.
+    pub fn finish(mut self, success: bool, summary: impl fmt::Display) {
+        self.finished = true;
+        self.transfers.end(&self.bar, self.board_id, success);
+
+        let received = self.bar.position();
+        let elapsed = self.bar.elapsed();
+        let rate = received as f64 / elapsed.as_secs_f64().max(0.001);
+        let stats = format!(
+            "({} in {}, {}/s)",
+            HumanBytes(received),
+            HumanDuration(elapsed),
+            HumanBytes(rate as u64)
+        );
+        status(format!("{} {}", summary, style(stats).dim()));
+    }
+}
+
+impl Drop for TransferBar {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.transfers.end(&self.bar, self.board_id, false);
+        }
+    }
+}
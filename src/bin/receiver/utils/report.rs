@@ -0,0 +1,125 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use console::Term;
+use indicatif::{MultiProgress, ProgressDrawTarget, TermLike};
+
+use super::tui;
+
+static HEADLESS: OnceLock<bool> = OnceLock::new();
+static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// Run without a terminal: no raw mode, no progress bars, no colors, and
+/// status lines go to the log instead of stdout.
+pub fn set_headless() {
+    let _ = HEADLESS.set(true);
+    console::set_colors_enabled(false);
+}
+
+pub fn is_headless() -> bool {
+    HEADLESS.get().copied().unwrap_or(false)
+}
+
+/// The progress bars of all transfers, drawn below the status lines.
+/// Headless or with the dashboard, nothing is drawn.
+pub fn progress() -> &'static MultiProgress {
+    PROGRESS.get_or_init(|| match is_headless() || tui::board().is_some() {
+        true => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        false => MultiProgress::with_draw_target(ProgressDrawTarget::term_like(Box::new(RawTerm(
+            Term::stdout(),
+        )))),
+    })
+}
+
+/// Print a status line for the user, above the progress bars.
+/// Headless, the line is logged instead. With the dashboard, it goes to
+/// the "Recent" pane.
+pub fn status(msg: impl fmt::Display) {
+    if is_headless() {
+        log::info!(target: "receiver", "{}", msg);
+    } else if let Some(board) = tui::board() {
+        board.log(msg.to_string());
+    } else if let Err(e) = progress().println(msg.to_string()) {
+        log::debug!("Failed to print status line: {}", e);
+    }
+}
+
+/// A terminal that also works in raw mode, where a line feed does not
+/// return the cursor to the start of the line.
+#[derive(Debug)]
+struct RawTerm(Term);
+
+impl TermLike for RawTerm {
+    fn width(&self) -> u16 {
+        self.0.size().1
+    }
+
+    fn height(&self) -> u16 {
+        self.0.size().0
+    }
+
+    fn move_cursor_up(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_up(n)
+    }
+
+    fn move_cursor_down(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_down(n)
+    }
+
+    fn move_cursor_right(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_right(n)
+    }
+
+    fn move_cursor_left(&self, n: usize) -> std::io::Result<()> {
+        self.0.move_cursor_left(n)
+    }
+
+    fn write_line(&self, s: &str) -> std::io::Result<()> {
+        self.write_str(s)?;
+        self.0.write_str("\r\n")
+    }
+
+    fn write_str(&self, s: &str) -> std::io::Result<()> {
+        self.0.write_str(&s.replace('\n', "\r\n"))
+    }
+
+    fn clear_line(&self) -> std::io::Result<()> {
+        self.0.clear_line()
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Set up logging. Headless, status lines are logged, so the default level
+/// is `info`. `RUST_LOG` overrides it either way.
+/// # Arguments
+/// - `log_file`: Append the log to this file instead of writing it to stderr.
+pub fn init_logger(log_file: Option<&Path>) -> anyhow::Result<()> {
+    let default_level = if is_headless() { "info" } else { "error" };
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level));
+
+    if let Some(path) = log_file {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open log file {:?}: {}", path, e))?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+
+    builder.init();
+    Ok(())
+}
+
+/// Tell systemd how the service is doing, if it runs under systemd.
+#[cfg(unix)]
+pub fn notify_systemd(state: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        log::warn!("Failed to notify systemd: {}", e);
+    }
+}
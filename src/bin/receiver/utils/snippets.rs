@@ -0,0 +1,99 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use clap::ValueEnum;
+use console::style;
+use serde::Deserialize;
+
+use super::report::status;
+
+/// What happens to received text snippets.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetMode {
+    /// Show them with the status lines
+    #[default]
+    Print,
+    /// Append them to the snippets file
+    Save,
+}
+
+/// Where received text snippets go.
+/// # Example
+/// ```
+/// let snippets = Snippets::new(SnippetMode::Save, PathBuf::from("inbox/snippets.txt"));
+/// snippets.deliver("laptop", "https://example.com")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Snippets {
+    mode: SnippetMode,
+    file: PathBuf,
+}
+
+impl Snippets {
+    pub fn new(mode: SnippetMode, file: PathBuf) -> Self {
+        Self { mode, file }
+    }
+
+    /// Print or save a snippet from `peer`.
+    /// # Returns
+    /// The snippets file if it was saved there.
+    pub fn deliver(&self, peer: &str, text: &str) -> anyhow::Result<Option<PathBuf>> {
+        match self.mode {
+            SnippetMode::Print => {
+                status(format!("Text from {}:", style(peer).bold()));
+                for line in printable(text).lines() {
+                    status(format!("  {}", line));
+                }
+                Ok(None)
+            }
+            SnippetMode::Save => {
+                append(&self.file, peer, text)?;
+                status(format!(
+                    "Text from {} ({} bytes) saved to {}",
+                    style(peer).bold(),
+                    text.len(),
+                    self.file.display()
+                ));
+                Ok(Some(self.file.clone()))
+            }
+        }
+    }
+}
+
+/// Append a snippet to `path`, below a line saying when and from whom.
+fn append(path: &Path, peer: &str, text: &str) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create directory {:?}: {}", dir, e))?;
+    }
+
+    let mut entry = format!(
+        "--- {} from {} ---\n{}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        peer,
+        text
+    );
+    if !entry.ends_with('\n') {
+        entry.push('\n');
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(entry.as_bytes()))
+        .map_err(|e| anyhow::anyhow!("Failed to write snippets file {:?}: {}", path, e))
+}
+
+/// Replace control characters, so a snippet cannot mess with the terminal.
+fn printable(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\n' | '\t' => c,
+            c if c.is_control() => '\u{fffd}',
+            c => c,
+        })
+        .collect()
+}
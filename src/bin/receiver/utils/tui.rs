@@ -0,0 +1,480 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use indicatif::{HumanBytes, HumanDuration};
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph, Sparkline};
+use tokio::sync::{Notify, oneshot};
+
+static BOARD: OnceLock<Arc<Board>> = OnceLock::new();
+
+/// How many status lines the "Recent" pane keeps.
+const RECENT_LINES: usize = 200;
+/// How many speed samples the graph of a transfer shows.
+const SPEED_SAMPLES: usize = 120;
+/// The time between two speed samples.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the screen is redrawn when no key is pressed.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Switch the receiver to the full-screen dashboard.
+/// Status lines, transfers and accept prompts go to it from now on.
+pub fn enable() -> Arc<Board> {
+    // Styled text would show up as escape codes
+    console::set_colors_enabled(false);
+    BOARD.get_or_init(|| Arc::new(Board::default())).clone()
+}
+
+/// The dashboard's state, if it is enabled.
+pub fn board() -> Option<&'static Arc<Board>> {
+    BOARD.get()
+}
+
+/// What the dashboard shows, shared between the client handlers that
+/// update it and the thread that draws it.
+#[derive(Default)]
+pub struct Board {
+    state: Mutex<State>,
+    /// The user pressed 'q', or the dashboard failed.
+    quit: Notify,
+    /// The server is shutting down, the dashboard should close.
+    closing: AtomicBool,
+}
+
+#[derive(Default)]
+struct State {
+    addresses: Vec<String>,
+    out_dir: PathBuf,
+    next_id: u64,
+    transfers: Vec<Transfer>,
+    prompts: Vec<Prompt>,
+    recent: VecDeque<String>,
+}
+
+struct Transfer {
+    id: u64,
+    peer: String,
+    name: String,
+    size: u64,
+    received: u64,
+    started: Instant,
+    /// `received` at the last speed sample.
+    sampled: u64,
+    /// Bytes per second, oldest first.
+    speeds: VecDeque<u64>,
+    cancel: Arc<Notify>,
+}
+
+/// A transfer waiting for the user to accept or reject it.
+struct Prompt {
+    peer: String,
+    fingerprint: String,
+    name: String,
+    size: u64,
+    reply: oneshot::Sender<bool>,
+}
+
+impl Board {
+    /// Show where the server listens and saves files.
+    pub fn set_info(&self, addresses: Vec<String>, out_dir: &Path) {
+        let mut state = self.state.lock().unwrap();
+        state.addresses = addresses;
+        state.out_dir = out_dir.to_path_buf();
+    }
+
+    /// Add a status line to the "Recent" pane.
+    pub fn log(&self, line: String) {
+        let mut state = self.state.lock().unwrap();
+        if state.recent.len() == RECENT_LINES {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(line);
+    }
+
+    /// Show a new transfer.
+    /// # Arguments
+    /// - `cancel`: Notified when the user cancels the transfer.
+    /// # Returns
+    /// The id to update the transfer with.
+    pub fn add_transfer(&self, peer: &str, name: &str, size: u64, cancel: Arc<Notify>) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.transfers.push(Transfer {
+            id,
+            peer: peer.to_string(),
+            name: name.to_string(),
+            size,
+            received: 0,
+            started: Instant::now(),
+            sampled: 0,
+            speeds: VecDeque::new(),
+            cancel,
+        });
+        id
+    }
+
+    /// Count `n` more bytes received by a transfer.
+    pub fn advance(&self, id: u64, n: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(transfer) = state.transfers.iter_mut().find(|t| t.id == id) {
+            transfer.received += n;
+        }
+    }
+
+    pub fn remove_transfer(&self, id: u64) {
+        self.state.lock().unwrap().transfers.retain(|t| t.id != id);
+    }
+
+    /// Queue an accept prompt.
+    /// # Returns
+    /// The user's answer, `true` to accept.
+    pub fn ask(
+        &self,
+        peer: &str,
+        fingerprint: &str,
+        name: &str,
+        size: u64,
+    ) -> oneshot::Receiver<bool> {
+        let (reply, answer) = oneshot::channel();
+        self.state.lock().unwrap().prompts.push(Prompt {
+            peer: peer.to_string(),
+            fingerprint: fingerprint.to_string(),
+            name: name.to_string(),
+            size,
+            reply,
+        });
+        answer
+    }
+
+    /// Wait until the user quits the dashboard.
+    pub async fn quit_requested(&self) {
+        self.quit.notified().await
+    }
+
+    /// Make the dashboard close and give the terminal back.
+    pub fn close(&self) {
+        self.closing.store(true, Ordering::Relaxed);
+    }
+
+    /// Take a speed sample of every transfer.
+    fn sample(&self, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        for transfer in &mut state.transfers {
+            let bytes = transfer.received - transfer.sampled;
+            transfer.sampled = transfer.received;
+            if transfer.speeds.len() == SPEED_SAMPLES {
+                transfer.speeds.pop_front();
+            }
+            transfer
+                .speeds
+                .push_back((bytes as f64 / elapsed.as_secs_f64()) as u64);
+        }
+    }
+}
+
+/// Which list the arrow keys move in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Prompts,
+    Transfers,
+}
+
+struct App {
+    board: Arc<Board>,
+    focus: Focus,
+    prompts: ListState,
+    selected_transfer: usize,
+}
+
+/// Draw the dashboard and handle keys until the user quits or the server
+/// closes it.
+pub fn run(board: Arc<Board>) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut app = App {
+        board: board.clone(),
+        focus: Focus::Prompts,
+        prompts: ListState::default(),
+        selected_transfer: 0,
+    };
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+
+    // Stop the server too, whether the user quit or drawing failed
+    board.quit.notify_one();
+    result
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        let mut last_sample = Instant::now();
+        while !self.board.closing.load(Ordering::Relaxed) {
+            terminal.draw(|frame| self.render(frame))?;
+
+            if event::poll(TICK)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && !self.handle_key(key.code)
+            {
+                break;
+            }
+
+            if last_sample.elapsed() >= SAMPLE_INTERVAL {
+                self.board.sample(last_sample.elapsed());
+                last_sample = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// # Returns
+    /// `false` when the user wants to quit.
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Prompts => Focus::Transfers,
+                    Focus::Transfers => Focus::Prompts,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Char('a') | KeyCode::Char('y') => self.answer(true),
+            KeyCode::Char('r') | KeyCode::Char('n') => self.answer(false),
+            KeyCode::Char('c') => self.cancel(),
+            KeyCode::Char('o') => self.open_out_dir(),
+            _ => {}
+        }
+        true
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let state = self.board.state.lock().unwrap();
+        match self.focus {
+            Focus::Prompts => {
+                let selected = step(
+                    self.prompts.selected().unwrap_or(0),
+                    delta,
+                    state.prompts.len(),
+                );
+                self.prompts.select(Some(selected));
+            }
+            Focus::Transfers => {
+                self.selected_transfer = step(self.selected_transfer, delta, state.transfers.len());
+            }
+        }
+    }
+
+    /// Accept or reject the selected prompt.
+    fn answer(&mut self, accept: bool) {
+        let mut state = self.board.state.lock().unwrap();
+        let index = self.prompts.selected().unwrap_or(0);
+        if index >= state.prompts.len() {
+            return;
+        }
+        let prompt = state.prompts.remove(index);
+        // The sender may have given up waiting meanwhile
+        let _ = prompt.reply.send(accept);
+    }
+
+    /// Cancel the selected transfer.
+    fn cancel(&mut self) {
+        let state = self.board.state.lock().unwrap();
+        if let Some(transfer) = state.transfers.get(self.selected_transfer) {
+            transfer.cancel.notify_one();
+        }
+    }
+
+    fn open_out_dir(&mut self) {
+        let out_dir = self.board.state.lock().unwrap().out_dir.clone();
+        let opener = if cfg!(target_os = "macos") {
+            "open"
+        } else if cfg!(windows) {
+            "explorer"
+        } else {
+            "xdg-open"
+        };
+        let opened = Command::new(opener)
+            .arg(&out_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Err(e) = opened {
+            self.board
+                .log(format!("Failed to open {}: {}", out_dir.display(), e));
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let board = self.board.clone();
+        let mut state = board.state.lock().unwrap();
+        // Forget prompts whose sender hung up
+        state.prompts.retain(|prompt| !prompt.reply.is_closed());
+
+        let prompts_height = state.prompts.len().clamp(1, 5) as u16 + 2;
+        let [header, prompts, transfers, recent, footer] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Length(prompts_height),
+            Constraint::Min(5),
+            Constraint::Percentage(30),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let info = vec![
+            Line::from(format!("Listening on: {}", state.addresses.join(", "))),
+            Line::from(format!("Saving to: {}", state.out_dir.display())),
+        ];
+        frame.render_widget(
+            Paragraph::new(info).block(Block::bordered().title(" deliver receiver ")),
+            header,
+        );
+
+        self.render_prompts(frame, prompts, &state);
+        self.render_transfers(frame, transfers, &state);
+
+        let lines: Vec<ListItem> = state
+            .recent
+            .iter()
+            .rev()
+            .map(|line| ListItem::new(line.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(lines).block(Block::bordered().title(" Recent ")),
+            recent,
+        );
+
+        let help = "a accept  r reject  c cancel  o open folder  \u{2191}\u{2193} select  Tab switch  q quit";
+        frame.render_widget(
+            Paragraph::new(help).style(Style::new().add_modifier(Modifier::DIM)),
+            footer,
+        );
+    }
+
+    fn render_prompts(&mut self, frame: &mut Frame, area: Rect, state: &State) {
+        let items: Vec<ListItem> = state
+            .prompts
+            .iter()
+            .map(|prompt| {
+                ListItem::new(format!(
+                    "{} ({}) wants to send {} ({})",
+                    prompt.peer,
+                    prompt.fingerprint,
+                    prompt.name,
+                    HumanBytes(prompt.size)
+                ))
+            })
+            .collect();
+        if self.prompts.selected().is_none_or(|i| i >= items.len()) {
+            self.prompts
+                .select((!items.is_empty()).then(|| items.len() - 1));
+        }
+
+        let title = format!(" Pending ({}) ", items.len());
+        let list = List::new(items)
+            .block(focused_block(title, self.focus == Focus::Prompts))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.prompts);
+    }
+
+    fn render_transfers(&mut self, frame: &mut Frame, area: Rect, state: &State) {
+        let title = format!(" Transfers ({}) ", state.transfers.len());
+        let block = focused_block(title, self.focus == Focus::Transfers);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        self.selected_transfer = self
+            .selected_transfer
+            .min(state.transfers.len().saturating_sub(1));
+
+        // Three lines per transfer: label, gauge and speed graph
+        let rows =
+            Layout::vertical(vec![Constraint::Length(3); state.transfers.len()]).split(inner);
+        for (index, (transfer, row)) in state.transfers.iter().zip(rows.iter()).enumerate() {
+            let [label, gauge, graph] = Layout::vertical([Constraint::Length(1); 3]).areas(*row);
+
+            let elapsed = transfer.started.elapsed();
+            let speed = transfer.speeds.back().copied().unwrap_or(0);
+            let eta = match speed {
+                0 => "-".to_string(),
+                speed => HumanDuration(Duration::from_secs(
+                    transfer.size.saturating_sub(transfer.received) / speed,
+                ))
+                .to_string(),
+            };
+            let mut style = Style::new().add_modifier(Modifier::BOLD);
+            if index == self.selected_transfer && self.focus == Focus::Transfers {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            frame.render_widget(
+                Line::from(vec![
+                    Span::styled(format!("{} {}", transfer.peer, transfer.name), style),
+                    Span::raw(format!(
+                        "  {}/s, ETA {}, running {}",
+                        HumanBytes(speed),
+                        eta,
+                        HumanDuration(elapsed)
+                    )),
+                ]),
+                label,
+            );
+
+            let ratio = match transfer.size {
+                0 => 1.0,
+                size => (transfer.received as f64 / size as f64).min(1.0),
+            };
+            frame.render_widget(
+                Gauge::default()
+                    .gauge_style(Style::new().fg(Color::Green))
+                    .ratio(ratio)
+                    .label(format!(
+                        "{}/{}",
+                        HumanBytes(transfer.received),
+                        HumanBytes(transfer.size)
+                    )),
+                gauge,
+            );
+
+            // The newest samples that fit, aligned to the right
+            let width = graph.width as usize;
+            let skip = transfer.speeds.len().saturating_sub(width);
+            let speeds: Vec<u64> = transfer.speeds.iter().skip(skip).copied().collect();
+            frame.render_widget(
+                Sparkline::default()
+                    .data(&speeds)
+                    .style(Style::new().fg(Color::Cyan)),
+                graph,
+            );
+        }
+    }
+}
+
+fn focused_block(title: String, focused: bool) -> Block<'static> {
+    let style = match focused {
+        true => Style::new().fg(Color::Yellow),
+        false => Style::new(),
+    };
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(style)
+        .title(title)
+}
+
+/// Move an index by `delta` within `0..len`.
+fn step(index: usize, delta: isize, len: usize) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    index.saturating_add_signed(delta).min(len - 1)
+}
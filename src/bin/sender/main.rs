@@ -5,22 +5,140 @@ pub mod utils;
 
 use clap::Parser;
 
-use crate::utils::args::Args;
-use crate::utils::get_addr_from_cache;
-use crate::utils::tcp_sender;
+use crate::utils::addr_cache::{AddrCache, LastResult};
+use crate::utils::archive::DirFilter;
+use crate::utils::args::{Args, Command};
+use crate::utils::history::show_history;
+use crate::utils::{
+    EXIT_INPUT_REQUIRED, InputRequired, get_peers_from_cache, manage_peers, send_text_to_all,
+    tcp_sender,
+};
 
+use std::io::{self, IsTerminal, Read};
 use std::path::Path;
+use std::process::ExitCode;
 
-fn main() -> anyhow::Result<()> {
+use console::style;
+
+use deliver::identity::{Device, Identity};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
     // ANCHOR: some init events
     env_logger::init();
     let args = Args::parse();
     // ANCHOR_END: some init events
 
+    match args.command {
+        Some(Command::Peers(command)) => return manage_peers(command).map(|()| ExitCode::SUCCESS),
+        Some(Command::History(history)) => {
+            return show_history(history).map(|()| ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
     // ANCHOR: cfg info
-    let args_file = Path::new(&args.file);
-    let ip_addr = get_addr_from_cache();
+    // `--file` is required unless a subcommand or `--text` is given
+    let text = match args.text.as_deref() {
+        Some("-") => Some(read_stdin()?),
+        text => text.map(str::to_string),
+    };
+    // Prompts need a terminal to read from and draw on
+    let interactive =
+        !args.non_interactive && io::stdin().is_terminal() && io::stderr().is_terminal();
+    let mut cache = AddrCache::load();
+    let peers = match get_peers_from_cache(&args, &mut cache, interactive) {
+        Ok(peers) => peers,
+        Err(e) if e.is::<InputRequired>() => {
+            eprintln!("Error: {}", e);
+            return Ok(ExitCode::from(EXIT_INPUT_REQUIRED));
+        }
+        Err(e) => return Err(e),
+    };
+    let identity = Identity::load()?;
     // ANCHOR_END: cfg info
 
-    tcp_sender(args_file, &ip_addr)
+    let results = match &text {
+        Some(text) => send_text_to_all(text, &peers, &identity).await?,
+        None => {
+            let filter = DirFilter {
+                exclude: args.exclude.clone(),
+                ignore_files: args.gitignore,
+            };
+            let target = Path::new(args.file.as_deref().unwrap());
+            tcp_sender(target, &filter, &peers, &identity).await?
+        }
+    };
+
+    // ANCHOR: remember the peers and how the transfers went
+    let mut summary = Vec::new();
+    for (mut peer, result) in peers.into_iter().zip(results) {
+        match &result {
+            Ok(receiver) => {
+                // Pin the receiver's key and name the peer after it
+                if peer.fingerprint.is_none() {
+                    peer.fingerprint = receiver.fingerprint().ok();
+                }
+                if peer.has_default_alias() {
+                    peer.alias = receiver.name.clone();
+                }
+                summary.push((peer.to_string(), result));
+                cache.record(peer, LastResult::Success);
+            }
+            // Only peers saved by an earlier successful transfer are updated
+            Err(e) if cache.contains(&peer) => {
+                let reason = e.to_string();
+                summary.push((peer.to_string(), result));
+                cache.record(peer, LastResult::Failed { reason });
+            }
+            Err(_) => {
+                log::debug!("Not saving {} after a failed transfer", peer);
+                summary.push((peer.to_string(), result));
+            }
+        }
+    }
+    cache.save();
+    // ANCHOR_END: remember the peers and how the transfers went
+
+    // A single transfer fails like it always did, several get a table
+    if summary.len() == 1 {
+        let (_, result) = summary.pop().unwrap();
+        return result.map(|_| ExitCode::SUCCESS);
+    }
+    print_summary(&summary);
+    Ok(match summary.iter().all(|(_, result)| result.is_ok()) {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
+}
+
+/// Print how the transfer to each receiver ended, one line per receiver.
+fn print_summary(summary: &[(String, anyhow::Result<Device>)]) {
+    let width = summary
+        .iter()
+        .map(|(peer, _)| peer.len())
+        .max()
+        .unwrap_or(0);
+    let sent = summary.iter().filter(|(_, result)| result.is_ok()).count();
+    println!();
+    println!("Sent to {} of {} receivers:", sent, summary.len());
+    for (peer, result) in summary {
+        let result = match result {
+            Ok(_) => style("sent".to_string()).green(),
+            Err(e) => style(format!("failed: {}", e)).red(),
+        };
+        println!("  {:<width$}  {}", peer, result, width = width);
+    }
+}
+
+/// Read the text to send from stdin, up to its end.
+fn read_stdin() -> anyhow::Result<String> {
+    if io::stdin().is_terminal() {
+        eprintln!("Reading the text from stdin, end it with Ctrl-D");
+    }
+    let mut text = String::new();
+    io::stdin()
+        .read_to_string(&mut text)
+        .map_err(|e| anyhow::anyhow!("Failed to read text from stdin: {}", e))?;
+    Ok(text)
 }
@@ -1,82 +1,311 @@
 pub mod addr_cache;
+pub mod archive;
 pub mod args;
+pub mod history;
 
-use clap::Parser;
 use dialoguer::Input;
 
-use addr_cache::AddrCache;
-use args::Args;
+use addr_cache::{AddrCache, Peer};
+use archive::{DirFilter, zip_directory};
+use args::{Args, PeersCommand};
+use deliver::addr::{join_host_port, normalize_host, resolve, split_host_port, validate_host};
 use deliver::cfg::Cfg;
+use deliver::discovery::discover;
+use deliver::mdns;
+use deliver::uri::DeliverUri;
 
-/// Get the IP address from command line arguments or cache.
-/// If not provided, prompt the user for input.
+/// How long to listen for receivers answering the discovery probe or mDNS query.
+const DISCOVERY_WAIT: Duration = Duration::from_millis(1000);
+
+/// The exit code when the receiver is not given and cannot be asked for.
+pub const EXIT_INPUT_REQUIRED: u8 = 3;
+
+/// The error when the sender would have to prompt, but must not.
+#[derive(Debug)]
+pub struct InputRequired(pub String);
+
+impl fmt::Display for InputRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InputRequired {}
+
+/// Get the peers to send to from command line arguments or cache.
+/// If none are provided, prompt the user for input.
+/// # Arguments
+/// * `args` - The parsed command line arguments.
+/// * `cache` - The saved peers, to look up `--to` and to offer in the menu.
+/// * `interactive` - Whether the user may be prompted. If not, the most
+///   recently used peer is taken, or an [`InputRequired`] error returned.
 /// # Returns
-/// A `String` representing the IP address and port in the format "{ip}:{port}".
+/// The `Peer`s to send to, either saved ones or new ones, each only once.
 ///
 /// # Example
 /// ```
-/// let ip_addr = get_addr_from_cache();
+/// let mut cache = AddrCache::load();
+/// let peers = get_peers_from_cache(&args, &mut cache, true)?;
 /// ```
-pub fn get_addr_from_cache() -> String {
-    let args = Args::parse();
-    let port = args.port.unwrap_or_else(|| {
-        let cfg = Cfg::load();
-        cfg.get_port()
-    });
-    let mut cache = AddrCache::load();
-    let ip_addr = match args.ip {
-        Some(ip) => {
-            // If an IP address is provided, use it
-            let ip_addr = format!("{}:{}", ip, port);
-            cache.add_addr(ip_addr.clone());
-            ip_addr
+pub fn get_peers_from_cache(
+    args: &Args,
+    cache: &mut AddrCache,
+    interactive: bool,
+) -> anyhow::Result<Vec<Peer>> {
+    let cfg = Cfg::load();
+    let port = args.port.unwrap_or_else(|| cfg.get_port());
+
+    let mut peers = Vec::new();
+    for target in &args.to {
+        // A `deliver://` URI names the receiver and its fingerprint at once
+        let peer = match DeliverUri::parse(target) {
+            Some(uri) => peer_from_uri(cache, uri),
+            // Otherwise a saved peer is named
+            None => cache
+                .get(target)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("No saved peer named {}", target))?,
+        };
+        peers.push(peer);
+    }
+    for ip in &args.ip {
+        // If an IP address is provided, use it
+        peers.push(peer_from_ip(cache, ip, port)?);
+    }
+    for name in &args.group {
+        let members = cfg
+            .get_group(name)
+            .ok_or_else(|| anyhow::anyhow!("No group named {} in sender.toml", name))?;
+        for member in members {
+            // Members are saved peers, or anything `--ip` takes
+            let peer = match cache.get(member) {
+                Some(peer) => peer.clone(),
+                None => peer_from_ip(cache, member, port)?,
+            };
+            peers.push(peer);
         }
-        None => {
-            // If no IP address is provided in cli, first read from history file
-            match cache.select_addr() {
-                Some(ip_addr) => ip_addr,
-                None => {
-                    // If history file does not exist or is empty, prompt user for IP address
-                    let ip: String = Input::new()
-                        .with_prompt("Enter server IP address")
-                        .interact_text()
-                        .unwrap();
-                    let ip_addr = format!("{}:{}", ip, port);
-                    cache.add_addr(ip_addr.clone());
-                    ip_addr
+    }
+    if !peers.is_empty() {
+        // Each receiver gets the payload once, however often it was named
+        let mut addrs = HashSet::new();
+        peers.retain(|peer| addrs.insert(peer.addr()));
+        return Ok(peers);
+    }
+
+    // Without anyone to ask, fall back to the peer used last
+    if !interactive {
+        let peer = cache.most_recent().cloned().ok_or_else(|| {
+            InputRequired(
+                "No receiver given and no saved peer to fall back to, \
+                 pass --ip, --to or --group"
+                    .to_string(),
+            )
+        })?;
+        println!("Sending to the most recent peer {}", style(&peer).bold());
+        return Ok(vec![peer]);
+    }
+
+    // If no IP address is provided in cli, look for receivers on the LAN
+    let browsing = thread::spawn(|| mdns::browse(DISCOVERY_WAIT));
+    let mut discovered = discover(DISCOVERY_WAIT).unwrap_or_else(|e| {
+        log::warn!("LAN discovery failed: {}", e);
+        Vec::new()
+    });
+    match browsing.join() {
+        Ok(Ok(browsed)) => {
+            for found in browsed {
+                if !discovered.iter().any(|f| f.is_same_receiver(&found)) {
+                    discovered.push(found);
                 }
             }
         }
+        Ok(Err(e)) => log::warn!("mDNS browsing failed: {}", e),
+        Err(_) => log::warn!("mDNS browsing panicked"),
+    }
+
+    // then offer them together with the history file
+    let selected = cache.select_addr(&discovered)?;
+    if !selected.is_empty() {
+        return Ok(selected);
+    }
+
+    // If history file does not exist or is empty, prompt user for IP address
+    let ip: String = Input::new()
+        .with_prompt("Enter server IP address")
+        .validate_with(|input: &String| check_input(input, port))
+        .interact_text()
+        .map_err(|e| anyhow::anyhow!("Failed to read the address: {}", e))?;
+    let peer = match DeliverUri::parse(&ip) {
+        Some(uri) => peer_from_uri(cache, uri),
+        None => cache.get_or_new(&normalize_host(&ip), port),
     };
+    Ok(vec![peer])
+}
+
+/// The peer at an address given on the command line, with or without a
+/// port, or a `deliver://` URI.
+fn peer_from_ip(cache: &AddrCache, ip: &str, port: u16) -> anyhow::Result<Peer> {
+    if let Some(uri) = DeliverUri::parse(ip) {
+        return Ok(peer_from_uri(cache, uri));
+    }
+    let (host, port) = split_host_port(ip).unwrap_or_else(|| (normalize_host(ip), port));
+    validate_host(&host)?;
+    Ok(cache.get_or_new(&host, port))
+}
+
+/// Check an address typed at the prompt, so typos can be fixed right away
+/// instead of failing the transfer later.
+fn check_input(input: &str, port: u16) -> Result<(), String> {
+    let (host, port) = match DeliverUri::parse(input) {
+        Some(uri) => (uri.host, uri.port),
+        None => (normalize_host(input), port),
+    };
+    resolve(&join_host_port(&host, port))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// The peer a `deliver://` URI points to.
+/// A fingerprint in the URI replaces the one saved for that address.
+fn peer_from_uri(cache: &AddrCache, uri: DeliverUri) -> Peer {
+    let mut peer = cache.get_or_new(&uri.host, uri.port);
+    if uri.fingerprint.is_some() {
+        peer.fingerprint = uri.fingerprint;
+    }
+    peer
+}
+
+/// Run one of the `peers` subcommands against the address cache.
+pub fn manage_peers(command: PeersCommand) -> anyhow::Result<()> {
+    let mut cache = AddrCache::load();
+    match command {
+        PeersCommand::List => {
+            cache.list();
+            return Ok(());
+        }
+        PeersCommand::Rename { alias, new_alias } => {
+            cache.rename(&alias, &new_alias)?;
+            println!("Renamed {} to {}", alias, style(&new_alias).bold());
+        }
+        PeersCommand::Pin { alias } => {
+            cache.set_pinned(&alias, true)?;
+            println!("Pinned {}", style(&alias).bold());
+        }
+        PeersCommand::Unpin { alias } => {
+            cache.set_pinned(&alias, false)?;
+            println!("Unpinned {}", style(&alias).bold());
+        }
+        PeersCommand::Rm { alias } => {
+            let peer = cache.remove(&alias)?;
+            println!("Deleted {}", style(peer).bold());
+        }
+    }
     cache.save();
-    ip_addr
+    Ok(())
 }
 
-use std::fs::File;
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use indicatif::{ProgressBar, ProgressStyle};
+use chrono::Utc;
+use console::style;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use sha2::{Digest, Sha256};
-use zip_extensions::*;
+use socket2::SockRef;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
 
+use deliver::history::{Direction, History, Outcome, Record};
+use deliver::identity::{Device, Identity, new_nonce};
 use deliver::pkg_info::PkgInfo;
+use deliver::proto::{
+    COMPACT_TEXT_LEN, Failure, Frame, MAX_TEXT_LEN, PROTOCOL_VERSION, SNIPPET_NAME, TransferFailed,
+    read_frame_async, write_frame_async,
+};
+use deliver::timeouts::{StallDetector, Timeouts, within};
+
+/// How many chunks may wait for a slow receiver before reading pauses.
+const FAN_OUT_BACKLOG: usize = 16;
+
+/// How much of the content is kept for receivers that accept late.
+const REPLAY_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// What the reader hands to the task sending to one receiver.
+#[derive(Clone)]
+enum Chunk {
+    Data(Arc<[u8]>),
+    /// All of the content was read, with this checksum.
+    End(String),
+    /// Reading failed, for this reason.
+    Abort(String),
+}
 
-/// Send a file to the specified IP address over TCP.
-/// Displays a progress bar during the transfer.
+/// A receiver being sent to, and how far the transfer to it got.
+struct Delivery {
+    addr: String,
+    label: String,
+    fingerprint: Option<String>,
+    /// The receiver's name and fingerprint, once it said hello
+    seen: Option<(String, String)>,
+    pb: Option<ProgressBar>,
+    result: Option<anyhow::Result<Device>>,
+}
+
+impl Delivery {
+    fn new(peer: &Peer) -> Self {
+        Self {
+            addr: peer.addr(),
+            label: peer.to_string(),
+            fingerprint: peer.fingerprint.clone(),
+            seen: None,
+            pb: None,
+            result: None,
+        }
+    }
+}
+
+/// Send a file to one or more receivers over TCP, to all of them at once.
+/// The file is read once, hashing it while it is sent; its checksum follows
+/// the content. Displays a progress bar per receiver during the transfer,
+/// and records every attempt in the transfer history whether it succeeds or
+/// not. Ctrl-C cancels the transfer.
 /// # Arguments
 /// * `sender_target` - A Path ref that holds the path of the file/dir to be sent.
-/// * `ip_addr` - A string slice that holds the IP address and port of the server.
+/// * `filter` - The files to leave out when sending a directory.
+/// * `peers` - The receivers to send to, with their pinned fingerprints if any.
+/// * `identity` - The device identity used to sign the handshake.
 /// # Returns
-/// An `anyhow::Result<()>` indicating success or failure.
+/// For each peer in order, its receiver's device or why sending to it failed.
+/// An error if the file could not be prepared for anyone.
 /// # Example
 /// ```
-/// tcp_sender(Path::new("path/to/file.txt"), "192.168.172.58:9000")?;
+/// let identity = Identity::load()?;
+/// let peers = [Peer::new("192.168.172.58", 9000)];
+/// tcp_sender(Path::new("path/to/file.txt"), &DirFilter::default(), &peers, &identity).await?;
 /// ```
-pub fn tcp_sender(sender_target: &Path, ip_addr: &str) -> anyhow::Result<()> {
+pub async fn tcp_sender(
+    sender_target: &Path,
+    filter: &DirFilter,
+    peers: &[Peer],
+    identity: &Identity,
+) -> anyhow::Result<Vec<anyhow::Result<Device>>> {
+    // Recorded in the history, unlike the archive a directory is sent as
+    let source = fs::canonicalize(sender_target).unwrap_or_else(|_| sender_target.to_path_buf());
+    let to = peers.iter().map(Peer::addr).collect::<Vec<_>>().join(", ");
+
     // ANCHOR: judge the sender_target is file or dir
     let file_type;
+    // What is left of a directory after the exclusions
+    let mut contents = None;
     let sender_target: PathBuf = if !sender_target.exists() {
         return Err(anyhow::anyhow!(
             "The specified path does not exist: {:?}",
@@ -85,7 +314,7 @@ pub fn tcp_sender(sender_target: &Path, ip_addr: &str) -> anyhow::Result<()> {
     } else {
         if sender_target.is_file() {
             // It's a file.
-            println!("Sending file: {:?} to {}", sender_target, ip_addr);
+            println!("Sending file: {:?} to {}", sender_target, to);
             file_type = "file";
             sender_target.to_owned()
         } else if sender_target.is_dir() {
@@ -101,14 +330,25 @@ pub fn tcp_sender(sender_target: &Path, ip_addr: &str) -> anyhow::Result<()> {
                 .join(dir_name.to_string())
                 .with_extension("uzip");
             let source_path = sender_target.to_path_buf();
-            zip_create_from_directory(&archive_path, &source_path)?;
+            let (archive, source, filter) =
+                (archive_path.clone(), source_path.clone(), filter.clone());
+            let packed =
+                tokio::task::spawn_blocking(move || zip_directory(&source, &archive, &filter))
+                    .await??;
             log::debug!(
                 "Created archive from {:?} at {:?}",
                 source_path,
                 archive_path
             );
 
-            println!("Sending directory: {:?} to {}", sender_target, ip_addr);
+            println!(
+                "Sending directory: {:?} ({} files, {}) to {}",
+                sender_target,
+                packed.files,
+                HumanBytes(packed.size),
+                to
+            );
+            contents = Some(packed);
 
             file_type = "directory";
             archive_path.to_owned()
@@ -132,61 +372,719 @@ pub fn tcp_sender(sender_target: &Path, ip_addr: &str) -> anyhow::Result<()> {
     };
     // ANCHOR_END: judge the sender_target is file or dir
 
-    let mut file = File::open(&sender_target)?;
-    let file_size = file.metadata()?.len();
+    let mut file = File::open(&sender_target).await?;
+    let file_size = file.metadata().await?.len();
 
-    // ANCHOR: calculate SHA256 of the file
-    let mut hasher = Sha256::new();
-    let mut file_copy = File::open(&sender_target)?;
-    let mut buf = [0u8; 8192];
-    loop {
-        let n = file_copy.read(&mut buf)?;
-        if n == 0 {
-            break;
+    let started = Utc::now();
+    let timer = Instant::now();
+    let timeouts = Cfg::load().get_timeouts();
+    let mut deliveries: Vec<Delivery> = peers.iter().map(Delivery::new).collect();
+    // Known once all of the content was read
+    let mut checksum = None;
+    let transfer = async {
+        // ANCHOR: offer the file to every receiver at once
+        let mut handshakes = JoinSet::new();
+        for (i, delivery) in deliveries.iter().enumerate() {
+            let addr = delivery.addr.clone();
+            let fingerprint = delivery.fingerprint.clone();
+            let identity = identity.clone();
+            let header = Frame::Header {
+                name: file_name.clone(),
+                size: file_size,
+                text: false,
+                contents,
+            };
+            handshakes.spawn(async move {
+                let mut seen = None;
+                let opened = open_transfer(
+                    &addr,
+                    &identity,
+                    fingerprint.as_deref(),
+                    &timeouts,
+                    &header,
+                    &mut seen,
+                )
+                .await;
+                (i, seen, opened)
+            });
+        }
+        // ANCHOR_END: offer the file to every receiver at once
+
+        // ANCHOR: read the file once, hashing it while it is sent
+        // Reading starts once the first receiver accepted, and each receiver
+        // gets the content as soon as it accepted, so none of them sits idle
+        // while another one's user is still deciding. One that accepts late
+        // first catches up on what was read, kept while offers are pending
+        // and up to REPLAY_LIMIT.
+        let bars = MultiProgress::new();
+        let mut sending = JoinSet::new();
+        let mut feeds: Vec<mpsc::Sender<Chunk>> = Vec::new();
+        let mut replay: Option<Vec<Arc<[u8]>>> = Some(Vec::new());
+        // The checksum, or why reading failed, once all of it was read
+        let mut last: Option<Chunk> = None;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut read: u64 = 0;
+        loop {
+            // Take in the receivers that answered meanwhile, without waiting
+            // for the others unless there is nobody to read for
+            let reading = last.is_none() && !feeds.is_empty();
+            let joined = match reading {
+                true => handshakes.try_join_next(),
+                false => handshakes.join_next().await,
+            };
+            if let Some(joined) = joined {
+                let (i, seen, opened) = joined?;
+                deliveries[i].seen = seen;
+                let (stream, receiver) = match opened {
+                    Ok(opened) => opened,
+                    Err(e) => {
+                        deliveries[i].result = Some(Err(e));
+                        continue;
+                    }
+                };
+                let Some(caught_up) = replay.clone() else {
+                    deliveries[i].result = Some(Err(anyhow::anyhow!(
+                        "Accepted after more than {} had been sent to the others",
+                        HumanBytes(REPLAY_LIMIT)
+                    )));
+                    continue;
+                };
+
+                // ANCHOR: send file content with progress bar
+                let pb = bars.add(ProgressBar::new(file_size));
+                pb.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{prefix}{msg} {wide_bar} {bytes}/{total_bytes} ({eta})")
+                        .unwrap()
+                        .progress_chars("=>-"),
+                );
+                // Tell the bars apart when there are several
+                if peers.len() > 1 {
+                    pb.set_prefix(format!("{}: ", deliveries[i].label));
+                }
+                pb.set_message(format!("Sending {}", format_name));
+                deliveries[i].pb = Some(pb.clone());
+
+                let (tx, rx) = mpsc::channel(FAN_OUT_BACKLOG);
+                match &last {
+                    // Everything is in the replay already
+                    Some(last) => {
+                        let _ = tx.send(last.clone()).await;
+                    }
+                    None => feeds.push(tx),
+                }
+                sending.spawn(async move {
+                    let sent = send_content(stream, caught_up, rx, &pb, &timeouts).await;
+                    (i, sent.map(|()| receiver))
+                });
+                // ANCHOR_END: send file content with progress bar
+                continue;
+            }
+            if handshakes.is_empty() {
+                // Nobody is left to catch up
+                replay = Some(Vec::new());
+            }
+            if !reading {
+                break;
+            }
+
+            let chunk = async {
+                if read == file_size {
+                    return Ok(None);
+                }
+                // Never send more than announced, even if the file grew
+                let want = (file_size - read).min(buf.len() as u64) as usize;
+                let n = file.read(&mut buf[..want]).await?;
+                if n == 0 {
+                    return Err(anyhow::anyhow!(
+                        "{} shrank to {} bytes while sending it",
+                        format_name,
+                        read
+                    ));
+                }
+                hasher.update(&buf[..n]);
+                read += n as u64;
+                anyhow::Ok(Some(Arc::<[u8]>::from(&buf[..n])))
+            }
+            .await;
+            let finished = match chunk {
+                Ok(data) => {
+                    if let Some(data) = data {
+                        match &mut replay {
+                            Some(_) if read > REPLAY_LIMIT => replay = None,
+                            Some(replay) if !handshakes.is_empty() => replay.push(data.clone()),
+                            _ => {}
+                        }
+                        let mut live = Vec::with_capacity(feeds.len());
+                        for feed in feeds.drain(..) {
+                            // A receiver that failed stopped listening
+                            if feed.send(Chunk::Data(data.clone())).await.is_ok() {
+                                live.push(feed);
+                            }
+                        }
+                        feeds = live;
+                    }
+                    (read == file_size).then(|| {
+                        let digest = hex::encode(std::mem::take(&mut hasher).finalize());
+                        checksum = Some(digest.clone());
+                        Chunk::End(digest)
+                    })
+                }
+                Err(e) => Some(Chunk::Abort(e.to_string())),
+            };
+            if let Some(finished) = finished {
+                for feed in feeds.drain(..) {
+                    let _ = feed.send(finished.clone()).await;
+                }
+                last = Some(finished);
+            }
+        }
+        // ANCHOR_END: read the file once, hashing it while it is sent
+
+        while let Some(joined) = sending.join_next().await {
+            let (i, result) = joined?;
+            deliveries[i].result = Some(result);
+        }
+        anyhow::Ok(())
+    };
+    // Whatever had not ended yet was interrupted
+    if let Err(e) = cancellable(transfer).await {
+        for delivery in deliveries.iter_mut().filter(|d| d.result.is_none()) {
+            delivery.result = Some(match e.is::<TransferFailed>() {
+                true => Err(interrupted()),
+                false => Err(anyhow::anyhow!("{}", e)),
+            });
         }
-        hasher.update(&buf[..n]);
     }
-    let checksum = hasher.finalize();
-    // ANCHOR_END: calculate SHA256 of the file
 
-    let mut stream = TcpStream::connect(ip_addr)?;
+    if let [
+        Delivery {
+            result: Some(Ok(_)),
+            ..
+        },
+    ] = deliveries.as_slice()
+    {
+        println!("Sent {}: {} ({} bytes)", file_type, format_name, file_size);
+    }
 
-    // ANCHOR: send file name length, name, size, and checksum
-    let name_len = file_name.len() as u16;
-    stream.write_all(&name_len.to_be_bytes())?;
+    // ANCHOR: record the transfer in the history
+    let duration_secs = timer.elapsed().as_secs_f64();
+    let mut results = Vec::with_capacity(deliveries.len());
+    for delivery in deliveries {
+        let result = delivery.result.unwrap_or_else(|| Err(interrupted()));
+        // Receivers that never got the content did not get its checksum either
+        let (transferred, sha256) = match delivery.pb {
+            Some(pb) => (pb.position(), checksum.clone().unwrap_or_default()),
+            None => (0, String::new()),
+        };
+        let (peer, receiver_fingerprint) = match delivery.seen {
+            Some((name, fingerprint)) => (name, Some(fingerprint)),
+            None => (delivery.addr.clone(), None),
+        };
+        History::record(&Record {
+            time: started,
+            direction: Direction::Sent,
+            peer,
+            fingerprint: receiver_fingerprint,
+            addr: delivery.addr,
+            name: format_name.to_string(),
+            path: source.display().to_string(),
+            size: file_size,
+            transferred: Some(transferred),
+            resumable: false,
+            sha256,
+            duration_secs,
+            outcome: match &result {
+                Ok(_) => Outcome::Success,
+                Err(e) => Outcome::from_error(e),
+            },
+        });
+        results.push(result);
+    }
+    // ANCHOR_END: record the transfer in the history
 
-    stream.write_all(file_name.as_bytes())?;
+    Ok(results)
+}
 
-    stream.write_all(&file_size.to_be_bytes())?;
+/// Write the content fed by the reader to one receiver, then its checksum,
+/// and wait for the receiver to verify it.
+/// # Arguments
+/// * `replay` - What was read before the receiver accepted, written first.
+/// * `chunks` - The content as it is read, ending with its checksum.
+/// * `pb` - The receiver's progress bar, at the bytes written so far.
+async fn send_content(
+    mut stream: TcpStream,
+    replay: Vec<Arc<[u8]>>,
+    mut chunks: mpsc::Receiver<Chunk>,
+    pb: &ProgressBar,
+    timeouts: &Timeouts,
+) -> anyhow::Result<()> {
+    let mut stall = StallDetector::new(timeouts);
+    let content = async {
+        let mut replay = replay.into_iter();
+        loop {
+            let next = match replay.next() {
+                Some(chunk) => Some(Chunk::Data(chunk)),
+                None => chunks.recv().await,
+            };
+            match next {
+                Some(Chunk::Data(chunk)) => {
+                    let write = async { Ok(stream.write_all(&chunk).await?) };
+                    within(timeouts.idle(), "sending data", write).await?;
+                    stall.record(chunk.len())?;
+                    pb.inc(chunk.len() as u64);
+                }
+                Some(Chunk::End(checksum)) => {
+                    return write_frame_async(&mut stream, &Frame::Trailer { checksum }).await;
+                }
+                Some(Chunk::Abort(reason)) => return Err(anyhow::anyhow!(reason)),
+                None => return Err(anyhow::anyhow!("Stopped reading the file")),
+            }
+        }
+    }
+    .await;
+    // Let the reader go on without this receiver
+    chunks.close();
 
-    stream.write_all(&checksum)?;
-    // ANCHOR_END: send file name length, name, size, and checksum
+    if let Err(e) = content {
+        pb.abandon_with_message("Send aborted");
 
-    // ANCHOR: send file content with progress bar
-    let pb = ProgressBar::new(file_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{msg} {wide_bar} {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .progress_chars("=>-"),
-    );
-    pb.set_message(format!("Sending {}", format_name));
-    // ANCHOR_END: send file content with progress bar
+        // The receiver may have told us why it gave up
+        let reason = read_frame_async(&mut stream);
+        if let Ok(Ok(Frame::Failed { failure, reason })) =
+            tokio::time::timeout(Duration::from_secs(1), reason).await
+        {
+            return Err(TransferFailed { failure, reason }.into());
+        }
+        return Err(e);
+    }
+    pb.finish_with_message("Send complete");
+
+    read_verdict(&mut stream, timeouts).await
+}
+
+/// Send a text snippet to the specified IP address over TCP, and record the
+/// attempt in the transfer history. Short snippets go inline in a single
+/// frame, longer ones like a file.
+/// # Arguments
+/// * `text` - The snippet, at most [`MAX_TEXT_LEN`] bytes.
+/// * `ip_addr` - A string slice that holds the IP address and port of the server.
+/// * `identity` - The device identity used to sign the handshake.
+/// * `fingerprint` - The receiver's pinned fingerprint, if any.
+/// # Returns
+/// The receiver's device on success.
+/// # Example
+/// ```
+/// let identity = Identity::load()?;
+/// send_text("https://example.com", "192.168.172.58:9000", &identity, None).await?;
+/// ```
+pub async fn send_text(
+    text: &str,
+    ip_addr: &str,
+    identity: &Identity,
+    fingerprint: Option<&str>,
+) -> anyhow::Result<Device> {
+    if text.len() > MAX_TEXT_LEN {
+        return Err(anyhow::anyhow!(
+            "The text is {} bytes, send more than {} bytes as a file",
+            text.len(),
+            MAX_TEXT_LEN
+        ));
+    }
+    let size = text.len() as u64;
+    let checksum = hex::encode(Sha256::digest(text.as_bytes()));
+    let compact = text.len() <= COMPACT_TEXT_LEN;
+    let offer = match compact {
+        true => Frame::Text {
+            text: text.to_string(),
+        },
+        false => Frame::Header {
+            name: SNIPPET_NAME.to_string(),
+            size,
+            text: true,
+            contents: None,
+        },
+    };
+    println!("Sending text ({} bytes) to {}", size, ip_addr);
 
-    // ANCHOR: send file content
+    let started = Utc::now();
+    let timer = Instant::now();
+    let mut seen = None;
     let mut sent: u64 = 0;
+    let transfer = async {
+        let timeouts = Cfg::load().get_timeouts();
+        let (mut stream, receiver) =
+            open_transfer(ip_addr, identity, fingerprint, &timeouts, &offer, &mut seen).await?;
+        if !compact {
+            let write = async { Ok(stream.write_all(text.as_bytes()).await?) };
+            within(timeouts.idle(), "sending data", write).await?;
+            let trailer = Frame::Trailer {
+                checksum: checksum.clone(),
+            };
+            write_frame_async(&mut stream, &trailer).await?;
+        }
+        sent = size;
+        read_verdict(&mut stream, &timeouts).await?;
+
+        println!("Sent text ({} bytes)", size);
+        Ok(receiver)
+    };
+    let result = cancellable(transfer).await;
+
+    // ANCHOR: record the snippet in the history
+    let (peer, receiver_fingerprint) = match seen {
+        Some((name, fingerprint)) => (name, Some(fingerprint)),
+        None => (ip_addr.to_string(), None),
+    };
+    History::record(&Record {
+        time: started,
+        direction: Direction::Sent,
+        peer,
+        fingerprint: receiver_fingerprint,
+        addr: ip_addr.to_string(),
+        name: SNIPPET_NAME.to_string(),
+        path: String::new(),
+        size,
+        transferred: Some(sent),
+        resumable: false,
+        sha256: checksum.clone(),
+        duration_secs: timer.elapsed().as_secs_f64(),
+        outcome: match &result {
+            Ok(_) => Outcome::Success,
+            Err(e) => Outcome::from_error(e),
+        },
+    });
+    // ANCHOR_END: record the snippet in the history
+
+    result
+}
+
+/// Send a text snippet to every peer at once, see [`send_text`].
+/// # Returns
+/// For each peer in order, its receiver's device or why sending to it failed.
+pub async fn send_text_to_all(
+    text: &str,
+    peers: &[Peer],
+    identity: &Identity,
+) -> anyhow::Result<Vec<anyhow::Result<Device>>> {
+    let text: Arc<str> = Arc::from(text);
+    let mut sends = JoinSet::new();
+    for (i, peer) in peers.iter().enumerate() {
+        let text = text.clone();
+        let addr = peer.addr();
+        let fingerprint = peer.fingerprint.clone();
+        let identity = identity.clone();
+        sends.spawn(async move {
+            let sent = send_text(&text, &addr, &identity, fingerprint.as_deref()).await;
+            (i, sent)
+        });
+    }
+
+    let mut results: Vec<_> = peers.iter().map(|_| None).collect();
+    while let Some(joined) = sends.join_next().await {
+        let (i, sent) = joined?;
+        results[i] = Some(sent);
+    }
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Run a transfer until it ends or the user presses Ctrl-C.
+/// Its connection is then dropped, which resets it, see [`open_stream`].
+async fn cancellable<T>(transfer: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    tokio::select! {
+        result = transfer => result,
+        _ = tokio::signal::ctrl_c() => {
+            println!();
+            Err(interrupted())
+        }
+    }
+}
+
+/// The error of a transfer the user cancelled with Ctrl-C.
+fn interrupted() -> anyhow::Error {
+    TransferFailed {
+        failure: Failure::Cancelled,
+        reason: "interrupted".to_string(),
+    }
+    .into()
+}
+
+/// Connect to the receiver, prove who we are and offer it a transfer.
+/// # Arguments
+/// * `fingerprint` - The receiver's pinned fingerprint, if any.
+/// * `offer` - The `Header` or `Text` frame describing the transfer.
+/// * `seen` - Set to the receiver's name and fingerprint once it said hello.
+/// # Returns
+/// The stream, ready for the content, and the receiver's device.
+async fn open_transfer(
+    ip_addr: &str,
+    identity: &Identity,
+    fingerprint: Option<&str>,
+    timeouts: &Timeouts,
+    offer: &Frame,
+    seen: &mut Option<(String, String)>,
+) -> anyhow::Result<(TcpStream, Device)> {
+    let (mut stream, receiver, receiver_nonce) = connect(ip_addr, timeouts).await?;
+
+    // ANCHOR: check the receiver against its pinned fingerprint
+    let receiver_fingerprint = receiver.fingerprint()?;
+    *seen = Some((receiver.name.clone(), receiver_fingerprint.clone()));
+    if let Some(expected) = fingerprint
+        && expected != receiver_fingerprint
+    {
+        return Err(anyhow::anyhow!(
+            "Receiver fingerprint changed: expected {}, got {}. \
+             If this is expected, delete the saved peer and try again.",
+            expected,
+            receiver_fingerprint
+        ));
+    }
+    // ANCHOR_END: check the receiver against its pinned fingerprint
+
+    // ANCHOR: handshake with the receiver
+    let nonce = new_nonce();
+    write_frame_async(
+        &mut stream,
+        &Frame::Identify {
+            device: identity.device(),
+            nonce: nonce.clone(),
+            signature: identity.sign_challenge(&receiver_nonce)?,
+        },
+    )
+    .await?;
+    // ANCHOR_END: handshake with the receiver
+
+    // ANCHOR: send file name and size
+    write_frame_async(&mut stream, offer).await?;
+    // ANCHOR_END: send file name and size
+
+    // ANCHOR: wait for the receiver to accept
+    let mut asked = false;
+    let answer = loop {
+        let frame = read_frame_async(&mut stream);
+        match within(
+            timeouts.header(),
+            "waiting for the receiver to accept",
+            frame,
+        )
+        .await?
+        {
+            // The receiver's user has to confirm the transfer first
+            Frame::Queued if !asked => {
+                println!("Waiting for the receiver to accept...");
+                asked = true;
+            }
+            Frame::Queued => {}
+            frame => break frame,
+        }
+    };
+    match answer {
+        Frame::Accept { signature } => receiver.verify_challenge(&nonce, &signature)?,
+        Frame::Reject { reason } => {
+            return Err(anyhow::anyhow!(
+                "Receiver rejected the transfer: {}",
+                reason
+            ));
+        }
+        frame => {
+            return Err(anyhow::anyhow!(
+                "Unexpected frame from receiver: {:?}",
+                frame
+            ));
+        }
+    }
+    println!(
+        "Connected to {} ({})",
+        style(&receiver.name).bold(),
+        receiver_fingerprint
+    );
+    // ANCHOR_END: wait for the receiver to accept
+
+    Ok((stream, receiver))
+}
+
+/// Wait for the receiver to tell whether the content arrived intact and
+/// was saved.
+async fn read_verdict(stream: &mut TcpStream, timeouts: &Timeouts) -> anyhow::Result<()> {
+    loop {
+        let verdict = read_frame_async(stream);
+        match within(
+            timeouts.header(),
+            "waiting for the receiver to verify the content",
+            verdict,
+        )
+        .await?
+        {
+            // Still saving, e.g. extracting a large directory
+            Frame::Queued => {}
+            Frame::Done => return Ok(()),
+            Frame::Failed { failure, reason } => {
+                return Err(TransferFailed { failure, reason }.into());
+            }
+            frame => {
+                return Err(anyhow::anyhow!(
+                    "Unexpected frame from receiver: {:?}",
+                    frame
+                ));
+            }
+        }
+    }
+}
+
+/// How often the sender retries a busy receiver before giving up.
+const MAX_BUSY_RETRIES: u32 = 5;
+/// Upper bound for the backoff between two retries, in seconds.
+const MAX_BACKOFF: u64 = 60;
+
+/// Connect to the receiver and wait for its `Hello`.
+/// If the receiver is busy, back off exponentially and try again.
+/// # Returns
+/// The stream, the receiver's device and the challenge nonce it sent.
+async fn connect(
+    ip_addr: &str,
+    timeouts: &Timeouts,
+) -> anyhow::Result<(TcpStream, Device, String)> {
+    let mut attempt = 0;
     loop {
-        let n = file.read(&mut buf)?;
-        if n == 0 {
+        let mut stream = open_stream(ip_addr, timeouts).await?;
+
+        let mut queued = false;
+        let greeting = loop {
+            let frame = read_frame_async(&mut stream);
+            match within(
+                timeouts.header(),
+                "waiting for the receiver's handshake",
+                frame,
+            )
+            .await?
+            {
+                Frame::Queued if !queued => {
+                    println!("Receiver is busy, waiting in its queue...");
+                    queued = true;
+                }
+                Frame::Queued => {}
+                frame => break frame,
+            }
+        };
+
+        match greeting {
+            Frame::Hello {
+                version,
+                device,
+                nonce,
+            } if version == PROTOCOL_VERSION => return Ok((stream, device, nonce)),
+            Frame::Hello { version, .. } => {
+                return Err(anyhow::anyhow!(
+                    "Receiver speaks protocol version {}, expected {}",
+                    version,
+                    PROTOCOL_VERSION
+                ));
+            }
+            Frame::Busy { retry_after } if attempt < MAX_BUSY_RETRIES => {
+                let backoff = (retry_after << attempt).min(MAX_BACKOFF);
+                println!(
+                    "{}",
+                    style(format!("Receiver is busy, retrying in {}s...", backoff)).yellow()
+                );
+                tokio::time::sleep(Duration::from_secs(backoff)).await;
+                attempt += 1;
+            }
+            Frame::Busy { .. } => {
+                return Err(anyhow::anyhow!(
+                    "Receiver is still busy after {} retries",
+                    MAX_BUSY_RETRIES
+                ));
+            }
+            frame => {
+                return Err(anyhow::anyhow!(
+                    "Unexpected frame from receiver: {:?}",
+                    frame
+                ));
+            }
+        }
+    }
+}
+
+/// How long to wait for a connection attempt before also trying the next
+/// address, as recommended for "happy eyeballs" by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Open a TCP connection, trying every address `ip_addr` resolves to.
+/// Attempts are started one after another, alternating between IPv6 and
+/// IPv4, without waiting for slow ones to time out; the first to connect wins.
+/// The connection is reset rather than closed when it is dropped, so an
+/// interrupted transfer looks cancelled to the receiver instead of truncated.
+async fn open_stream(ip_addr: &str, timeouts: &Timeouts) -> anyhow::Result<TcpStream> {
+    // The system resolver and the mDNS lookup both block
+    let target = ip_addr.to_string();
+    let resolved = tokio::task::spawn_blocking(move || resolve(&target)).await??;
+    let mut addrs = interleave_families(resolved).into_iter().peekable();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut running = 0;
+    let mut last_err = None;
+
+    loop {
+        if let Some(addr) = addrs.next() {
+            let tx = tx.clone();
+            let limit = timeouts.connect();
+            tokio::spawn(async move {
+                let connect = async { Ok(TcpStream::connect(addr).await?) };
+                let attempt = within(limit, &format!("connecting to {}", addr), connect).await;
+                // Late winners are dropped, closing their connection
+                let _ = tx.send((addr, attempt));
+            });
+            running += 1;
+        }
+        if running == 0 {
             break;
         }
-        stream.write_all(&buf[..n])?;
-        sent += n as u64;
-        pb.set_position(sent);
+
+        let attempt = if addrs.peek().is_some() {
+            tokio::time::timeout(CONNECTION_ATTEMPT_DELAY, rx.recv())
+                .await
+                .ok()
+                .flatten()
+        } else {
+            rx.recv().await
+        };
+        match attempt {
+            Some((addr, Ok(stream))) => {
+                log::debug!("Connected to {}", addr);
+                SockRef::from(&stream).set_linger(Some(Duration::ZERO))?;
+                return Ok(stream);
+            }
+            Some((addr, Err(e))) => {
+                log::debug!("Failed to connect to {}: {}", addr, e);
+                running -= 1;
+                last_err = Some(e);
+            }
+            // Still waiting, start the next attempt
+            None => {}
+        }
     }
-    pb.finish_with_message("Send complete");
-    // ANCHOR_END: send file content
 
-    println!("Sent {}: {} ({} bytes)", file_type, format_name, file_size);
-    Ok(())
+    match last_err {
+        Some(e) => Err(anyhow::anyhow!("Failed to connect to {}: {}", ip_addr, e)),
+        None => Err(anyhow::anyhow!(
+            "{} did not resolve to any address",
+            ip_addr
+        )),
+    }
+}
+
+/// Order addresses IPv6, IPv4, IPv6, ... keeping the resolver's order
+/// within each family.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| addr.is_ipv6());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut ordered = Vec::new();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
 }
@@ -1,30 +1,133 @@
 use std::collections::VecDeque;
+use std::fmt;
 use std::fs;
 
-use dialoguer::Select;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Local, Utc};
+use console::style;
+use dialoguer::{MultiSelect, Select};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use deliver::pkg_info::PkgInfo;
+use deliver::addr::{host_of, join_host_port, split_host_port};
 use deliver::cfg::Cfg;
+use deliver::discovery::Found;
+use deliver::pkg_info::PkgInfo;
+
+/// How the last transfer to a peer went.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LastResult {
+    Success,
+    Failed { reason: String },
+}
+
+/// A receiver the sender has talked to before.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Peer {
+    pub alias: String,
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_result: Option<LastResult>,
+    /// The receiver's device fingerprint, pinned after the first transfer.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Pinned peers are listed first and never evicted from the history.
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl Peer {
+    /// A new peer, named after its host until we learn the receiver's name.
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            alias: host.to_string(),
+            host: host.to_string(),
+            port,
+            last_used: None,
+            last_result: None,
+            fingerprint: None,
+            pinned: false,
+        }
+    }
+
+    /// Parse a legacy "{ip}:{port}" history entry.
+    /// It is named after the whole address, as several may share a host.
+    fn from_addr(addr: &str) -> Option<Self> {
+        let (host, port) = split_host_port(addr)?;
+        let mut peer = Self::new(&host, port);
+        peer.alias = addr.to_string();
+        Some(peer)
+    }
+
+    /// Whether the peer is still named after its address.
+    pub fn has_default_alias(&self) -> bool {
+        self.alias == self.host || self.alias == self.addr()
+    }
 
-/// A cache for storing the last 5 used IP addresses.
+    /// The "{ip}:{port}" to connect to, "[{ip}]:{port}" for IPv6.
+    pub fn addr(&self) -> String {
+        join_host_port(&self.host, self.port)
+    }
+
+    fn same_addr(&self, other: &Peer) -> bool {
+        self.host == other.host && self.port == other.port
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.has_default_alias() {
+            write!(f, "{}", self.addr())
+        } else {
+            write!(f, "{} ({})", self.alias, self.addr())
+        }
+    }
+}
+
+/// Entries of `addr_cache.json` are either peers or, in files written by
+/// older versions, plain "{ip}:{port}" strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPeer {
+    Legacy(String),
+    Peer(Peer),
+}
+
+fn deserialize_history<'de, D>(deserializer: D) -> Result<VecDeque<Peer>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let stored = Vec::<StoredPeer>::deserialize(deserializer)?;
+    Ok(stored
+        .into_iter()
+        .filter_map(|entry| match entry {
+            StoredPeer::Legacy(addr) => Peer::from_addr(&addr),
+            StoredPeer::Peer(peer) => Some(peer),
+        })
+        .collect())
+}
+
+/// A cache for storing the recently used peers.
 /// The cache is stored in a JSON file in the cache directory.
-/// The most recently used address is at the back of the deque.
-/// When adding a new address, if it already exists, it is moved to the back.
-/// If the cache is full, the oldest address (front) is removed.
+/// The most recently used peer is at the back of the deque.
+/// When recording a transfer to a known peer, it is moved to the back.
+/// If the cache is full, the oldest peer that is not pinned is removed.
 /// The cache is loaded from the file when the program starts and saved to the file when the program exits.
-/// The user can select a previously used address from a list or enter a new one.
-/// The cache is limited to 5 addresses.
+/// The user can select a previously used peer from a list or enter a new one.
+/// The cache is limited to `max_history` peers from the config, plus pinned ones.
 /// # Example
 /// ```
 /// let mut cache = AddrCache::load();
-/// cache.load();
-/// cache.add_addr("127.0.0.1:9000".to_string());
+/// let peer = Peer::new("127.0.0.1", 9000);
+/// cache.record(peer, LastResult::Success);
 /// cache.save();
 /// ```
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AddrCache {
-    history: VecDeque<String>,
+    #[serde(deserialize_with = "deserialize_history")]
+    history: VecDeque<Peer>,
 }
 
 impl AddrCache {
@@ -66,46 +169,230 @@ impl AddrCache {
         }
     }
 
-    /// Add an IP address to the history.
-    /// If the IP address already exists, move it to the most recent position.
-    /// If the history is full (5 addresses), remove the oldest one.
+    /// Find a peer by its alias.
+    pub fn get(&self, alias: &str) -> Option<&Peer> {
+        self.history.iter().find(|p| p.alias == alias)
+    }
+
+    /// Whether a peer of this address is saved, even one that was migrated
+    /// from an older cache and never used since.
+    pub fn contains(&self, peer: &Peer) -> bool {
+        self.history.iter().any(|p| p.same_addr(peer))
+    }
+
+    /// Find a peer by its address, or make a new one.
+    pub fn get_or_new(&self, host: &str, port: u16) -> Peer {
+        let peer = Peer::new(host, port);
+        self.history
+            .iter()
+            .find(|p| p.same_addr(&peer))
+            .cloned()
+            .unwrap_or(peer)
+    }
+
+    /// Record a transfer to a peer and move it to the most recent position.
+    /// If the history is full, remove the oldest peer that is not pinned.
     /// # Arguments
-    /// * `addr` - The IP address to add. (due to consumer requirement, it is a String)
-    pub fn add_addr(&mut self, addr: String) {
+    /// * `peer` - The peer as it was used, possibly with a newly learned fingerprint.
+    /// * `result` - How the transfer went.
+    pub fn record(&mut self, mut peer: Peer, result: LastResult) {
         // Get the max history size from config
         let max_history = Cfg::load().get_history_size();
 
         // Remove if already exists
-        if let Some(pos) = self.history.iter().position(|x| *x == addr) {
+        if let Some(pos) = self.history.iter().position(|p| p.same_addr(&peer)) {
             self.history.remove(pos);
         }
 
-        // Remove the oldest if full
-        if self.history.len() == max_history {
-            self.history.pop_front();
+        peer.alias = self.unique_alias(&peer.alias);
+        peer.last_used = Some(Utc::now());
+        peer.last_result = Some(result);
+        self.history.push_back(peer);
+
+        // Remove the oldest unpinned peers if full
+        while self.history.iter().filter(|p| !p.pinned).count() > max_history {
+            match self.history.iter().position(|p| !p.pinned) {
+                Some(pos) => self.history.remove(pos),
+                None => break,
+            };
+        }
+    }
+
+    /// Append "-2", "-3", ... to `alias` until no other peer uses it.
+    fn unique_alias(&self, alias: &str) -> String {
+        let mut candidate = alias.to_string();
+        let mut n = 2;
+        while self.get(&candidate).is_some() {
+            candidate = format!("{}-{}", alias, n);
+            n += 1;
         }
+        candidate
+    }
+
+    pub fn rename(&mut self, alias: &str, new_alias: &str) -> anyhow::Result<()> {
+        if self.get(new_alias).is_some() {
+            return Err(anyhow::anyhow!("A peer named {} already exists", new_alias));
+        }
+        self.get_mut(alias)?.alias = new_alias.to_string();
+        Ok(())
+    }
+
+    pub fn set_pinned(&mut self, alias: &str, pinned: bool) -> anyhow::Result<()> {
+        self.get_mut(alias)?.pinned = pinned;
+        Ok(())
+    }
 
-        self.history.push_back(addr);
+    pub fn remove(&mut self, alias: &str) -> anyhow::Result<Peer> {
+        let pos = self
+            .history
+            .iter()
+            .position(|p| p.alias == alias)
+            .ok_or_else(|| anyhow::anyhow!("No saved peer named {}", alias))?;
+        Ok(self.history.remove(pos).unwrap())
     }
 
-    pub fn select_addr(&mut self) -> Option<String> {
+    fn get_mut(&mut self, alias: &str) -> anyhow::Result<&mut Peer> {
+        self.history
+            .iter_mut()
+            .find(|p| p.alias == alias)
+            .ok_or_else(|| anyhow::anyhow!("No saved peer named {}", alias))
+    }
+
+    /// Pinned peers first, then the others from the most recent one.
+    fn sorted(&self) -> Vec<&Peer> {
+        let mut peers: Vec<&Peer> = self.history.iter().rev().collect();
+        peers.sort_by_key(|p| !p.pinned);
+        peers
+    }
+
+    /// Print all saved peers, one per line.
+    pub fn list(&self) {
         if self.history.is_empty() {
-            return None;
+            println!("No saved peers yet.");
+            return;
         }
 
-        let mut selections: Vec<&String> = self.history.iter().collect();
-        let other = String::from("Enter a new IP address");
-        selections.push(&other);
+        for peer in self.sorted() {
+            println!("{}", describe(peer));
+        }
+    }
+
+    /// The peer used last, if any.
+    pub fn most_recent(&self) -> Option<&Peer> {
+        self.history.back()
+    }
+
+    /// Let the user pick a saved peer or a discovered receiver, or several
+    /// of them to send to at once.
+    /// # Arguments
+    /// * `discovered` - Receivers found on the LAN, listed after the saved peers.
+    /// # Returns
+    /// The chosen peers, or none if the user wants to enter a new address.
+    pub fn select_addr(&mut self, discovered: &[Found]) -> anyhow::Result<Vec<Peer>> {
+        if self.history.is_empty() && discovered.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut peers: Vec<Peer> = self.sorted().into_iter().cloned().collect();
+        let mut selections: Vec<String> = peers.iter().map(describe).collect();
+        for found in discovered {
+            let mut peer = self.get_or_new(&host_of(&found.addr), found.addr.port());
+            if self.contains(&peer) {
+                // Already listed as a saved peer
+                continue;
+            }
+            peer.alias = found.announcement.name.clone();
+            peer.fingerprint = Some(found.announcement.fingerprint.clone());
+
+            selections.push(format!("{} - discovered on LAN", peer));
+            peers.push(peer);
+        }
+        let several = peers.len() > 1;
+        if several {
+            selections.push(String::from("Send to several of them"));
+        }
+        selections.push(String::from("Enter a new IP address"));
+
         let selection = Select::new()
-            .with_prompt("Select a previously used IP address")
+            .with_prompt("Select a saved peer or a discovered receiver")
             .items(&selections)
-            .default(selections.len() - 2)
+            .default(0)
             .interact()
-            .unwrap();
+            .map_err(|e| anyhow::anyhow!("Failed to select a peer: {}", e))?;
 
-        if selection == selections.len() - 1 {
-            return None;
+        if several && selection == peers.len() {
+            let chosen = MultiSelect::new()
+                .with_prompt("Select the receivers with Space, confirm with Enter")
+                .items(&selections[..peers.len()])
+                .interact()
+                .map_err(|e| anyhow::anyhow!("Failed to select the peers: {}", e))?;
+            if chosen.is_empty() {
+                return Err(anyhow::anyhow!("No receiver selected"));
+            }
+            return Ok(chosen.into_iter().map(|i| peers[i].clone()).collect());
         }
-        Some(selections[selection].to_string())
+        Ok(peers.get(selection).cloned().into_iter().collect())
+    }
+}
+
+/// One line describing a peer: alias, address, last use and result.
+fn describe(peer: &Peer) -> String {
+    let pin = if peer.pinned { "*" } else { " " };
+    let last_used = peer
+        .last_used
+        .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "never".to_string());
+    let result = match &peer.last_result {
+        Some(LastResult::Success) => style("ok".to_string()).green(),
+        Some(LastResult::Failed { reason }) => style(format!("failed: {}", reason)).red(),
+        None => style(String::new()),
+    };
+
+    format!(
+        "{} {:<16} {:<30} {} {}",
+        pin,
+        peer.alias,
+        peer.addr(),
+        last_used,
+        result
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_cache_still_loads() {
+        let cache: AddrCache =
+            serde_json::from_str(r#"{"history":["10.0.0.2:9000","[::1]:9000","bad"]}"#).unwrap();
+        let peers: Vec<_> = cache
+            .history
+            .iter()
+            .map(|p| (p.alias.as_str(), p.host.as_str(), p.port))
+            .collect();
+        assert_eq!(
+            peers,
+            [
+                ("10.0.0.2:9000", "10.0.0.2", 9000),
+                ("[::1]:9000", "::1", 9000)
+            ]
+        );
+        assert!(cache.history.iter().all(Peer::has_default_alias));
+        // Never used since, but saved all the same
+        assert!(cache.contains(&Peer::new("::1", 9000)));
+        assert!(!cache.contains(&Peer::new("::1", 9001)));
+    }
+
+    #[test]
+    fn legacy_and_current_entries_mix() {
+        let cache: AddrCache = serde_json::from_str(
+            r#"{"history":["10.0.0.2:9000",{"alias":"laptop","host":"10.0.0.3","port":9000,"pinned":true}]}"#,
+        )
+        .unwrap();
+        assert_eq!(cache.history.len(), 2);
+        let laptop = cache.get("laptop").unwrap();
+        assert_eq!(laptop.addr(), "10.0.0.3:9000");
+        assert!(laptop.pinned);
     }
 }
@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use ignore::WalkBuilder;
+use ignore::overrides::OverrideBuilder;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use deliver::proto::Contents;
+
+/// The ignore file that is always honored, in any directory of the tree.
+pub const DELIVERIGNORE: &str = ".deliverignore";
+
+/// Which files of a directory are left out when sending it.
+/// # Example
+/// ```
+/// let filter = DirFilter {
+///     exclude: vec!["target/".to_string(), "*.log".to_string()],
+///     ignore_files: true,
+/// };
+/// let contents = zip_directory(Path::new("project"), Path::new("project.uzip"), &filter)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DirFilter {
+    /// Globs in `.gitignore` syntax, relative to the directory.
+    pub exclude: Vec<String>,
+    /// Honor `.gitignore` and `.ignore` files, and leave out `.git`.
+    pub ignore_files: bool,
+}
+
+/// Pack `dir` into a zip archive at `archive`, without what `filter` and
+/// any `.deliverignore` files exclude. Files are stored uncompressed.
+/// # Returns
+/// How many files went into the archive and their total size.
+pub fn zip_directory(dir: &Path, archive: &Path, filter: &DirFilter) -> anyhow::Result<Contents> {
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in &filter.exclude {
+        // Override globs select files, a leading "!" leaves them out instead
+        overrides
+            .add(&format!("!{}", glob))
+            .map_err(|e| anyhow::anyhow!("Invalid --exclude pattern {:?}: {}", glob, e))?;
+    }
+    let overrides = overrides
+        .build()
+        .map_err(|e| anyhow::anyhow!("Invalid --exclude pattern: {}", e))?;
+
+    let ignore_files = filter.ignore_files;
+    let walk = WalkBuilder::new(dir)
+        .overrides(overrides)
+        .add_custom_ignore_filename(DELIVERIGNORE)
+        .hidden(false)
+        .follow_links(true)
+        .git_ignore(ignore_files)
+        .git_exclude(ignore_files)
+        .git_global(false)
+        .ignore(ignore_files)
+        // A .gitignore counts outside of a git checkout too
+        .require_git(false)
+        .filter_entry(move |entry| !(ignore_files && entry.file_name() == ".git"))
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut zip = ZipWriter::new(
+        File::create(archive)
+            .map_err(|e| anyhow::anyhow!("Failed to create archive {:?}: {}", archive, e))?,
+    );
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut contents = Contents::default();
+    for entry in walk {
+        let entry = entry.map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", dir, e))?;
+        let path = entry.path();
+        // The directory itself is the root of the archive
+        let Ok(relative) = path.strip_prefix(dir) else {
+            continue;
+        };
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let name = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if path.is_dir() {
+            zip.add_directory(name, options)?;
+        } else if path.is_file() {
+            let mut file = File::open(path)
+                .map_err(|e| anyhow::anyhow!("Failed to open {:?}: {}", path, e))?;
+            zip.start_file(name, options)?;
+            contents.size += io::copy(&mut file, &mut zip)
+                .map_err(|e| anyhow::anyhow!("Failed to archive {:?}: {}", path, e))?;
+            contents.files += 1;
+        }
+    }
+    zip.finish()?;
+
+    log::debug!(
+        "Archived {} files ({} bytes) from {:?}",
+        contents.files,
+        contents.size,
+        dir
+    );
+    Ok(contents)
+}
@@ -1,17 +1,119 @@
-use clap::Parser;
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use deliver::history::Direction;
 
 #[derive(Parser, Debug)]
-#[command(version, author, about, long_about = None)]
+#[command(version, author, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
     /// The file(include file and directory) to send
-    #[arg(short, long)]
-    pub file: String,
+    #[arg(short, long, required_unless_present = "text")]
+    pub file: Option<String>,
+
+    /// Send this text instead of a file, "-" reads it from stdin
+    #[arg(long, conflicts_with = "file")]
+    pub text: Option<String>,
 
-    /// The server IP address
+    /// Leave what matches this glob out of a directory, e.g. "target/" or "*.log".
+    /// Repeat it for several patterns
+    #[arg(long, value_name = "GLOB", conflicts_with = "text")]
+    pub exclude: Vec<String>,
+
+    /// Also leave out what .gitignore and .ignore files exclude, and .git itself
+    #[arg(long, conflicts_with = "text")]
+    pub gitignore: bool,
+
+    /// The server IP address, optionally with a :port, or a deliver://host:port?fp=... URI.
+    /// Repeat it to send to several receivers at once
     #[arg(short, long)]
-    pub ip: Option<String>,
+    pub ip: Vec<String>,
 
     /// The server port
     #[arg(short, long)]
     pub port: Option<u16>,
+
+    /// The alias of a saved peer to send to, or a deliver:// URI.
+    /// Repeat it to send to several receivers at once
+    #[arg(short, long, value_name = "ALIAS")]
+    pub to: Vec<String>,
+
+    /// Send to every receiver of a group from the [groups] of sender.toml
+    #[arg(short, long, value_name = "NAME")]
+    pub group: Vec<String>,
+
+    /// Never prompt: without --ip, --to or --group, send to the most recently used peer.
+    /// Implied when stdin or stderr is not a terminal
+    #[arg(long)]
+    pub non_interactive: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage the saved peers
+    #[command(subcommand)]
+    Peers(PeersCommand),
+    /// List, filter and export the transfers of both sender and receiver
+    History(HistoryArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PeersCommand {
+    /// List the saved peers, pinned ones first
+    List,
+    /// Give a saved peer a new alias
+    Rename { alias: String, new_alias: String },
+    /// Keep a peer in the history for good
+    Pin { alias: String },
+    /// Let a pinned peer expire from the history again
+    Unpin { alias: String },
+    /// Delete a saved peer
+    Rm { alias: String },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+    /// Only transfers with this peer, matching its name, fingerprint or address
+    #[arg(long)]
+    pub peer: Option<String>,
+
+    /// Only sent or received transfers
+    #[arg(long, value_name = "sent|received")]
+    pub direction: Option<Direction>,
+
+    /// Only files or directories whose name contains this
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Only transfers on or after this day, e.g. 2025-06-30
+    #[arg(long, value_name = "DATE")]
+    pub since: Option<NaiveDate>,
+
+    /// Only transfers before this day
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<NaiveDate>,
+
+    /// Only failed transfers
+    #[arg(long)]
+    pub failed: bool,
+
+    /// Only the last N matching transfers
+    #[arg(short = 'n', long, value_name = "N")]
+    pub last: Option<usize>,
+
+    /// How to print the transfers
+    #[arg(long, value_enum, default_value_t = HistoryFormat::Table)]
+    pub format: HistoryFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// One line per transfer, for reading
+    Table,
+    /// One JSON record per line, as stored
+    Json,
+    /// Comma-separated values with a header line
+    Csv,
 }
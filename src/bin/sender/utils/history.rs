@@ -0,0 +1,130 @@
+use chrono::Local;
+use console::style;
+use indicatif::{HumanBytes, HumanDuration};
+
+use deliver::history::{Direction, History, Outcome, Record};
+
+use super::args::{HistoryArgs, HistoryFormat};
+
+/// Print the transfers that match the filters of `args`, oldest first.
+pub fn show_history(args: HistoryArgs) -> anyhow::Result<()> {
+    let mut records: Vec<Record> = History::load()?
+        .into_iter()
+        .filter(|record| matches(record, &args))
+        .collect();
+    if let Some(last) = args.last {
+        records.drain(..records.len().saturating_sub(last));
+    }
+
+    match args.format {
+        HistoryFormat::Table if records.is_empty() => println!("No transfers found."),
+        HistoryFormat::Table => {
+            for record in &records {
+                println!("{}", describe(record));
+            }
+        }
+        HistoryFormat::Json => {
+            for record in &records {
+                println!("{}", serde_json::to_string(record)?);
+            }
+        }
+        HistoryFormat::Csv => {
+            println!(
+                "time,direction,peer,fingerprint,addr,name,path,size,sha256,duration_secs,result,failure,reason"
+            );
+            for record in &records {
+                println!("{}", to_csv(record));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn matches(record: &Record, args: &HistoryArgs) -> bool {
+    let day = record.time.with_timezone(&Local).date_naive();
+    let peer_matches = |peer: &str| {
+        record.peer.contains(peer)
+            || record.addr.contains(peer)
+            || record
+                .fingerprint
+                .as_deref()
+                .is_some_and(|fp| fp.contains(peer))
+    };
+
+    args.peer.as_deref().is_none_or(peer_matches)
+        && args
+            .direction
+            .is_none_or(|direction| record.direction == direction)
+        && args
+            .name
+            .as_deref()
+            .is_none_or(|name| record.name.contains(name))
+        && args.since.is_none_or(|since| day >= since)
+        && args.until.is_none_or(|until| day < until)
+        && (!args.failed || record.outcome != Outcome::Success)
+}
+
+fn describe(record: &Record) -> String {
+    let direction = match record.direction {
+        Direction::Sent => "->",
+        Direction::Received => "<-",
+    };
+    let result = match &record.outcome {
+        Outcome::Success => style("ok".to_string()).green(),
+        Outcome::Failed { reason, .. } if record.resumable => {
+            style(format!("failed: {}, kept to resume", reason)).red()
+        }
+        Outcome::Failed { reason, .. } => style(format!("failed: {}", reason)).red(),
+    };
+
+    format!(
+        "{} {} {:<16} {:<24} {:>10} {:>12} {} {}",
+        record.time.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+        direction,
+        record.peer,
+        record.name,
+        HumanBytes(record.size).to_string(),
+        HumanDuration(record.duration()).to_string(),
+        style(&record.sha256[..record.sha256.len().min(12)]).dim(),
+        result
+    )
+}
+
+fn to_csv(record: &Record) -> String {
+    let (result, failure, reason) = match &record.outcome {
+        Outcome::Success => ("success", None, ""),
+        Outcome::Failed { reason, failure } => ("failed", *failure, reason.as_str()),
+    };
+    let fields = [
+        record.time.to_rfc3339(),
+        record.direction.to_string(),
+        record.peer.clone(),
+        record.fingerprint.clone().unwrap_or_default(),
+        record.addr.clone(),
+        record.name.clone(),
+        record.path.clone(),
+        record.size.to_string(),
+        record.sha256.clone(),
+        format!("{:.3}", record.duration_secs),
+        result.to_string(),
+        failure
+            .map(|failure| failure.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        reason.to_string(),
+    ];
+    fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Quote a CSV field if it needs it, as in RFC 4180.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -1,13 +1,25 @@
+use std::collections::BTreeMap;
 use std::fs;
 
 use serde::{Deserialize, Serialize};
 
 use crate::pkg_info::PkgInfo;
+use crate::timeouts::Timeouts;
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct Cfg {
     default_port: u16,
     max_history: usize,
+    timeouts: Timeouts,
+    /// Named sets of receivers to send to at once, by alias, address or URI.
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for Cfg {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cfg {
@@ -15,6 +27,8 @@ impl Cfg {
         Self {
             default_port: 9000,
             max_history: 5,
+            timeouts: Timeouts::default(),
+            groups: BTreeMap::new(),
         }
     }
 
@@ -25,10 +39,12 @@ impl Cfg {
         log::debug!("Loading config from {:?}", path);
 
         if let Ok(data) = fs::read_to_string(&path) {
-            toml::from_str(&data).unwrap_or_else(|_| {
-                log::error!("Failed to parse config file, using default config");
+            let mut cfg: Cfg = toml::from_str(&data).unwrap_or_else(|e| {
+                log::error!("Failed to parse config file, using default config: {}", e);
                 Cfg::new()
-            })
+            });
+            cfg.timeouts = cfg.timeouts.or_defaults();
+            cfg
         } else {
             log::debug!("Config file not found, using default config");
 
@@ -44,6 +60,15 @@ impl Cfg {
         self.max_history
     }
 
+    pub fn get_timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// The members of the group `name`, if it is configured.
+    pub fn get_group(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(name).map(Vec::as_slice)
+    }
+
     pub fn set_port(&mut self, port: u16) {
         self.default_port = port;
     }
@@ -0,0 +1,249 @@
+//! LAN auto-discovery of running receivers.
+//!
+//! The sender broadcasts a `Probe` datagram to [`DISCOVERY_PORT`] on every
+//! local subnet, and sends it to the IPv6 all-nodes group `ff02::1` on every
+//! interface. Each receiver that listens there answers with an `Announce`
+//! holding its device name, TCP port and version.
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// The UDP port receivers listen on for discovery probes.
+pub const DISCOVERY_PORT: u16 = 9009;
+
+/// The link-local all-nodes multicast group, which every IPv6 host joins.
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Probe,
+    Announce(Announcement),
+}
+
+/// What a receiver tells about itself.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Announcement {
+    pub name: String,
+    /// The TCP port the receiver accepts transfers on.
+    pub port: u16,
+    pub version: String,
+    pub fingerprint: String,
+    /// Payload kinds the receiver accepts, see [`crate::proto::CAPABILITIES`].
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// A receiver found on the local network.
+#[derive(Debug, Clone)]
+pub struct Found {
+    /// Where to connect for a transfer.
+    pub addr: SocketAddr,
+    pub announcement: Announcement,
+}
+
+/// Answer discovery probes over IPv4 and IPv6 until the task is dropped.
+/// # Arguments
+/// * `announcement` - What to answer with.
+pub async fn serve(announcement: Announcement) -> anyhow::Result<()> {
+    let reply = serde_json::to_vec(&Message::Announce(announcement))?;
+
+    let v4 = bind_shared(SocketAddr::new(
+        Ipv4Addr::UNSPECIFIED.into(),
+        DISCOVERY_PORT,
+    ))?;
+    let v6 = match bind_shared(SocketAddr::new(
+        Ipv6Addr::UNSPECIFIED.into(),
+        DISCOVERY_PORT,
+    )) {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            // IPv6 may be disabled on this host
+            log::warn!("Not answering discovery probes over IPv6: {}", e);
+            None
+        }
+    };
+
+    log::debug!("Answering discovery probes on port {}", DISCOVERY_PORT);
+
+    match v6 {
+        Some(v6) => {
+            tokio::try_join!(answer_probes(v4, &reply), answer_probes(v6, &reply))?;
+        }
+        None => answer_probes(v4, &reply).await?,
+    }
+    Ok(())
+}
+
+async fn answer_probes(socket: UdpSocket, reply: &[u8]) -> anyhow::Result<()> {
+    let socket = tokio::net::UdpSocket::from_std(socket)?;
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let (n, peer) = socket.recv_from(&mut buf).await?;
+        match serde_json::from_slice(&buf[..n]) {
+            Ok(Message::Probe) => {
+                log::debug!("Discovery probe from {}", peer);
+                socket.send_to(reply, peer).await?;
+            }
+            Ok(Message::Announce(_)) => {}
+            Err(e) => log::debug!("Ignoring malformed datagram from {}: {}", peer, e),
+        }
+    }
+}
+
+/// Broadcast a probe on every local subnet and collect the answers.
+/// # Arguments
+/// * `wait` - How long to listen for answers.
+/// # Example
+/// ```no_run
+/// # use std::time::Duration;
+/// # use deliver::discovery::discover;
+/// for found in discover(Duration::from_millis(800))? {
+///     println!("{} at {}", found.announcement.name, found.addr);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn discover(wait: Duration) -> anyhow::Result<Vec<Found>> {
+    let probe = serde_json::to_vec(&Message::Probe)?;
+    let deadline = Instant::now() + wait;
+
+    let v4 = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    v4.set_broadcast(true)?;
+    for target in broadcast_addrs() {
+        if let Err(e) = v4.send_to(&probe, (target, DISCOVERY_PORT)) {
+            log::debug!("Failed to probe {}: {}", target, e);
+        }
+    }
+
+    let v6 = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+        .inspect_err(|e| log::debug!("Not probing over IPv6: {}", e))
+        .ok();
+    if let Some(v6) = &v6 {
+        for scope_id in multicast_scopes() {
+            let target = SocketAddrV6::new(ALL_NODES, DISCOVERY_PORT, 0, scope_id);
+            if let Err(e) = v6.send_to(&probe, target) {
+                log::debug!("Failed to probe {}: {}", target, e);
+            }
+        }
+    }
+
+    // Listen on both sockets at once, then merge the answers
+    let (mut found, found_v6) = thread::scope(|scope| {
+        let answers_v6 = v6.as_ref().map(|v6| scope.spawn(|| collect(v6, deadline)));
+        let found = collect(&v4, deadline);
+        let found_v6 = answers_v6.and_then(|handle| handle.join().ok());
+        (found, found_v6.unwrap_or_default())
+    });
+
+    // A dual-stack receiver answers on both, keep the first answer
+    for candidate in found_v6 {
+        if !found.iter().any(|f| f.is_same_receiver(&candidate)) {
+            found.push(candidate);
+        }
+    }
+
+    Ok(found)
+}
+
+impl Found {
+    /// Whether two answers come from the same receiver, possibly over
+    /// different addresses.
+    pub fn is_same_receiver(&self, other: &Found) -> bool {
+        self.addr == other.addr
+            || (self.announcement.fingerprint == other.announcement.fingerprint
+                && self.announcement.port == other.announcement.port)
+    }
+}
+
+/// Collect `Announce` answers on `socket` until `deadline`.
+fn collect(socket: &UdpSocket, deadline: Instant) -> Vec<Found> {
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+    let mut buf = [0u8; 1024];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        if socket
+            .set_read_timeout(Some(left.max(Duration::from_millis(1))))
+            .is_err()
+        {
+            break;
+        }
+
+        let Ok((n, peer)) = socket.recv_from(&mut buf) else {
+            break;
+        };
+        if let Ok(Message::Announce(announcement)) = serde_json::from_slice(&buf[..n]) {
+            let addr = match peer {
+                SocketAddr::V6(v6) => SocketAddr::V6(SocketAddrV6::new(
+                    *v6.ip(),
+                    announcement.port,
+                    0,
+                    v6.scope_id(),
+                )),
+                SocketAddr::V4(v4) => SocketAddr::new((*v4.ip()).into(), announcement.port),
+            };
+            if seen.insert(addr) {
+                log::debug!("Discovered {} at {}", announcement.name, addr);
+                found.push(Found { addr, announcement });
+            }
+        }
+    }
+    found
+}
+
+/// The indexes of all interfaces with an IPv6 address, to send link-local
+/// multicast on each of them.
+fn multicast_scopes() -> Vec<u32> {
+    let mut scopes = Vec::new();
+    if let Ok(interfaces) = if_addrs::get_if_addrs() {
+        for interface in interfaces {
+            if let if_addrs::IfAddr::V6(_) = interface.addr
+                && let Some(index) = interface.index
+                && !scopes.contains(&index)
+            {
+                scopes.push(index);
+            }
+        }
+    }
+    scopes
+}
+
+/// The limited broadcast address plus the directed broadcast address of
+/// every interface, as some systems only route one of them.
+fn broadcast_addrs() -> Vec<Ipv4Addr> {
+    let mut targets = vec![Ipv4Addr::BROADCAST];
+    if let Ok(interfaces) = if_addrs::get_if_addrs() {
+        for interface in interfaces {
+            if let if_addrs::IfAddr::V4(v4) = interface.addr
+                && let Some(broadcast) = v4.broadcast
+                && !targets.contains(&broadcast)
+            {
+                targets.push(broadcast);
+            }
+        }
+    }
+    targets
+}
+
+/// Bind a UDP socket that other receivers on this host may bind as well.
+fn bind_shared(addr: SocketAddr) -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        // IPv4 is served by a socket of its own
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    if addr.is_ipv4() {
+        socket.set_broadcast(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::pkg_info::PkgInfo;
+use crate::proto::{Failure, TransferFailed};
+
+/// Whether a transfer was sent or received by this device.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Sent => write!(f, "sent"),
+            Direction::Received => write!(f, "received"),
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sent" => Ok(Direction::Sent),
+            "received" => Ok(Direction::Received),
+            _ => Err(anyhow::anyhow!("Unknown direction: {}", s)),
+        }
+    }
+}
+
+/// How a transfer ended.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failed {
+        reason: String,
+        /// How the transfer failed, if it got as far as the content.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure: Option<Failure>,
+    },
+}
+
+impl Outcome {
+    /// The outcome of a transfer that ended with `error`.
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        Outcome::Failed {
+            reason: error.to_string(),
+            failure: error
+                .downcast_ref::<TransferFailed>()
+                .map(|failed| failed.failure),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "success"),
+            Outcome::Failed { reason, .. } => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// One transfer, as recorded in the history file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    /// When the transfer started.
+    pub time: DateTime<Utc>,
+    pub direction: Direction,
+    /// The other device's name, or its address if it never told us.
+    pub peer: String,
+    /// The other device's fingerprint, if the handshake got that far.
+    pub fingerprint: Option<String>,
+    /// The other device's address.
+    pub addr: String,
+    /// The file or directory name.
+    pub name: String,
+    /// Where the file was sent from or saved to.
+    pub path: String,
+    pub size: u64,
+    /// How many bytes of the content were sent or received, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transferred: Option<u64>,
+    /// Whether what arrived of a truncated file was kept at `path` to
+    /// resume from.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resumable: bool,
+    /// The SHA-256 of the content, as sent after it.
+    /// Empty if the transfer ended before that.
+    pub sha256: String,
+    pub duration_secs: f64,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl Record {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.duration_secs.max(0.0))
+    }
+}
+
+/// The transfers of both sender and receiver, one JSON record per line in
+/// `history.jsonl` under the cache directory. Records are only ever
+/// appended.
+pub struct History;
+
+impl History {
+    fn path() -> PathBuf {
+        PkgInfo::new().get_cache_dir().join("history.jsonl")
+    }
+
+    /// Append a record to the history file.
+    /// Each record is written with a single write, so concurrent transfers
+    /// do not interleave their lines.
+    pub fn append(record: &Record) -> anyhow::Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| anyhow::anyhow!("Failed to create cache directory: {}", e))?;
+        }
+
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| anyhow::anyhow!("Failed to write history file {:?}: {}", path, e))
+    }
+
+    /// Append a record, logging instead of failing, as a transfer should
+    /// not fail over its history.
+    pub fn record(record: &Record) {
+        if let Err(e) = Self::append(record) {
+            log::error!("{}", e);
+        }
+    }
+
+    /// Load all records, oldest first. Malformed lines are skipped.
+    pub fn load() -> anyhow::Result<Vec<Record>> {
+        let path = Self::path();
+
+        log::debug!("Loading history from {:?}", path);
+
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to read history file {:?}: {}",
+                    path,
+                    e
+                ));
+            }
+        };
+
+        let records = data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(index, line)| match serde_json::from_str(line) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    log::warn!("Skipping history line {}: {}", index + 1, e);
+                    None
+                }
+            })
+            .collect();
+        Ok(records)
+    }
+}
@@ -0,0 +1,161 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::pkg_info::PkgInfo;
+
+/// Prefix mixed into every signed challenge, so a handshake signature
+/// can never be replayed as a signature over something else.
+const CHALLENGE_CONTEXT: &[u8] = b"deliver handshake v2\0";
+
+/// The public half of a device identity, as exchanged in the handshake.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Device {
+    pub name: String,
+    /// Hex encoded Ed25519 public key.
+    pub public_key: String,
+}
+
+impl Device {
+    /// The SHA256 fingerprint of the device key, e.g. `SHA256:1f0c...`.
+    pub fn fingerprint(&self) -> anyhow::Result<String> {
+        Ok(fingerprint(&self.verifying_key()?))
+    }
+
+    /// Check that `signature` is this device's signature over `nonce`.
+    pub fn verify_challenge(&self, nonce: &str, signature: &str) -> anyhow::Result<()> {
+        let key = self.verifying_key()?;
+        let signature: [u8; 64] = hex::decode(signature)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Malformed signature"))?;
+
+        key.verify(&challenge(nonce)?, &Signature::from_bytes(&signature))
+            .map_err(|_| anyhow::anyhow!("Invalid signature from device {}", self.name))
+    }
+
+    fn verifying_key(&self) -> anyhow::Result<VerifyingKey> {
+        let bytes: [u8; 32] = hex::decode(&self.public_key)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Malformed public key"))?;
+
+        Ok(VerifyingKey::from_bytes(&bytes)?)
+    }
+}
+
+/// The long-term Ed25519 identity of this installation.
+/// The secret key is generated on first use and stored hex encoded in
+/// `identity.key` under the config directory.
+/// # Example
+/// ```no_run
+/// # use deliver::identity::Identity;
+/// let identity = Identity::load()?;
+/// println!("This device: {}", identity.fingerprint());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct Identity {
+    signing_key: SigningKey,
+    device_name: String,
+}
+
+impl Identity {
+    pub fn load() -> anyhow::Result<Self> {
+        let mut path = PkgInfo::new().get_config_dir();
+        path.push("identity.key");
+
+        log::debug!("Loading device identity from {:?}", path);
+
+        let signing_key = match fs::read_to_string(&path) {
+            Ok(data) => {
+                let secret: [u8; 32] = hex::decode(data.trim())?
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Malformed identity key in {:?}", path))?;
+                SigningKey::from_bytes(&secret)
+            }
+            Err(_) => {
+                let signing_key = SigningKey::generate(&mut OsRng);
+                Self::save(&path, &signing_key)?;
+
+                log::info!("Generated a new device identity at {:?}", path);
+
+                signing_key
+            }
+        };
+
+        Ok(Self {
+            signing_key,
+            device_name: device_name(),
+        })
+    }
+
+    fn save(path: &PathBuf, signing_key: &SigningKey) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // the secret key must only be readable by its owner
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(path)?;
+        writeln!(file, "{}", hex::encode(signing_key.to_bytes()))?;
+        Ok(())
+    }
+
+    pub fn device(&self) -> Device {
+        Device {
+            name: self.device_name.clone(),
+            public_key: hex::encode(self.signing_key.verifying_key().to_bytes()),
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.signing_key.verifying_key())
+    }
+
+    /// Sign a challenge nonce received from the peer.
+    pub fn sign_challenge(&self, nonce: &str) -> anyhow::Result<String> {
+        let signature = self.signing_key.sign(&challenge(nonce)?);
+        Ok(hex::encode(signature.to_bytes()))
+    }
+}
+
+/// Generate a fresh random challenge nonce, hex encoded.
+pub fn new_nonce() -> String {
+    let mut nonce = [0u8; 32];
+    OsRng.fill_bytes(&mut nonce);
+    hex::encode(nonce)
+}
+
+fn challenge(nonce: &str) -> anyhow::Result<Vec<u8>> {
+    let nonce = hex::decode(nonce)?;
+    if nonce.len() != 32 {
+        return Err(anyhow::anyhow!("Malformed challenge nonce"));
+    }
+
+    Ok([CHALLENGE_CONTEXT, &nonce].concat())
+}
+
+fn fingerprint(key: &VerifyingKey) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    format!("SHA256:{}", hex::encode(&digest[..16]))
+}
+
+/// The name this device presents to its peers, which is the host name.
+fn device_name() -> String {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
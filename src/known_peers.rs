@@ -0,0 +1,133 @@
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use crate::pkg_info::PkgInfo;
+
+/// The trust decision recorded for a device key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// Transfers from this device are accepted without asking.
+    Allow,
+    /// Transfers from this device are always rejected.
+    Deny,
+}
+
+impl fmt::Display for Trust {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trust::Allow => write!(f, "allow"),
+            Trust::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+impl FromStr for Trust {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Trust::Allow),
+            "deny" => Ok(Trust::Deny),
+            _ => Err(anyhow::anyhow!("Unknown trust value: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KnownPeer {
+    pub fingerprint: String,
+    pub trust: Trust,
+    /// The device name last seen with this key, for humans only.
+    pub name: String,
+}
+
+/// The list of known devices, similar to SSH `known_hosts`.
+/// It is stored in `known_peers` under the config directory, one device per line:
+/// ```text
+/// # <fingerprint> <allow|deny> <device name>
+/// SHA256:1f0c8a... allow laptop
+/// ```
+/// Lines starting with `#` and malformed lines are ignored.
+#[derive(Debug, Default)]
+pub struct KnownPeers {
+    peers: Vec<KnownPeer>,
+}
+
+impl KnownPeers {
+    pub fn load() -> Self {
+        let mut path = PkgInfo::new().get_config_dir();
+        path.push("known_peers");
+
+        log::debug!("Loading known peers from {:?}", path);
+
+        let Ok(data) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+
+        let peers = data
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut parts = line.splitn(3, char::is_whitespace);
+                let fingerprint = parts.next()?.to_string();
+                let trust = match parts.next()?.parse() {
+                    Ok(trust) => trust,
+                    Err(e) => {
+                        log::warn!("Skipping known peer {}: {}", fingerprint, e);
+                        return None;
+                    }
+                };
+                let name = parts.next().unwrap_or_default().trim().to_string();
+
+                Some(KnownPeer {
+                    fingerprint,
+                    trust,
+                    name,
+                })
+            })
+            .collect();
+
+        Self { peers }
+    }
+
+    pub fn save(&self) {
+        let mut path = PkgInfo::new().get_config_dir();
+
+        // create the directory if it does not exist
+        if let Err(e) = fs::create_dir_all(&path) {
+            log::error!("Failed to create config directory: {}", e);
+
+            return;
+        }
+
+        path.push("known_peers");
+
+        let mut data = String::from("# <fingerprint> <allow|deny> <device name>\n");
+        for peer in &self.peers {
+            data.push_str(&format!(
+                "{} {} {}\n",
+                peer.fingerprint, peer.trust, peer.name
+            ));
+        }
+
+        if let Err(e) = fs::write(&path, data) {
+            log::error!("Failed to write known peers file: {}", e);
+        }
+    }
+
+    pub fn get(&self, fingerprint: &str) -> Option<&KnownPeer> {
+        self.peers.iter().find(|p| p.fingerprint == fingerprint)
+    }
+
+    /// Record a trust decision for a device, replacing any previous one.
+    pub fn set(&mut self, fingerprint: &str, trust: Trust, name: &str) {
+        self.peers.retain(|p| p.fingerprint != fingerprint);
+        self.peers.push(KnownPeer {
+            fingerprint: fingerprint.to_string(),
+            trust,
+            name: name.to_string(),
+        });
+    }
+}
@@ -1,2 +1,12 @@
 pub mod pkg_info;
 pub mod cfg;
+pub mod identity;
+pub mod known_peers;
+pub mod proto;
+pub mod timeouts;
+pub mod discovery;
+pub mod mdns;
+pub mod addr;
+pub mod uri;
+
+pub mod history;
\ No newline at end of file
@@ -0,0 +1,161 @@
+//! mDNS / DNS-SD advertisement of receivers as `_deliver._tcp`.
+//!
+//! Unlike our own [`crate::discovery`], this is understood by standard
+//! tools such as `avahi-browse -r _deliver._tcp`.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use mdns_sd::{HostnameResolutionEvent, ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::addr::is_link_local;
+use crate::discovery::{Announcement, Found};
+
+pub const SERVICE_TYPE: &str = "_deliver._tcp.local.";
+
+/// Keeps the receiver's service published for as long as it lives.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Advertisement {
+    /// Publish a receiver with TXT records for its name, version,
+    /// fingerprint and capabilities.
+    pub fn publish(announcement: &Announcement) -> anyhow::Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+
+        let instance = format!("{}-{}", announcement.name, announcement.port);
+        let host_name = format!("{}.local.", announcement.name);
+        let capabilities = announcement.capabilities.join(",");
+        let properties = [
+            ("name", announcement.name.as_str()),
+            ("version", announcement.version.as_str()),
+            ("fp", announcement.fingerprint.as_str()),
+            ("caps", capabilities.as_str()),
+        ];
+
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance,
+            &host_name,
+            "",
+            announcement.port,
+            &properties[..],
+        )?
+        .enable_addr_auto();
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service)?;
+
+        log::debug!("Published {} via mDNS", fullname);
+
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            log::debug!("Failed to unregister {}: {}", self.fullname, e);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Browse for `_deliver._tcp` services and resolve them to addresses.
+/// # Arguments
+/// * `wait` - How long to collect answers.
+pub fn browse(wait: Duration) -> anyhow::Result<Vec<Found>> {
+    let daemon = ServiceDaemon::new()?;
+    let events = daemon.browse(SERVICE_TYPE)?;
+
+    let mut found: Vec<Found> = Vec::new();
+    let deadline = Instant::now() + wait;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = events.recv_timeout(left) else {
+            break;
+        };
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+
+        let Some(ip) = pick_addr(&info) else {
+            continue;
+        };
+        let addr = SocketAddr::new(ip, info.get_port());
+        if found.iter().any(|f| f.addr == addr) {
+            continue;
+        }
+
+        let property = |key: &str| info.get_property_val_str(key).unwrap_or_default();
+        let announcement = Announcement {
+            name: property("name").to_string(),
+            port: info.get_port(),
+            version: property("version").to_string(),
+            fingerprint: property("fp").to_string(),
+            capabilities: property("caps")
+                .split(',')
+                .filter(|cap| !cap.is_empty())
+                .map(str::to_string)
+                .collect(),
+        };
+
+        log::debug!("Resolved {} at {}", info.get_fullname(), addr);
+
+        found.push(Found { addr, announcement });
+    }
+
+    let _ = daemon.shutdown();
+    Ok(found)
+}
+
+/// Look up the addresses of a `.local` host name over mDNS, for systems
+/// whose resolver does not do that itself.
+/// # Arguments
+/// * `host` - A name such as `laptop.local`.
+/// * `wait` - How long to wait for an answer.
+pub fn resolve_hostname(host: &str, wait: Duration) -> anyhow::Result<Vec<IpAddr>> {
+    let daemon = ServiceDaemon::new()?;
+    let hostname = format!("{}.", host.trim_end_matches('.'));
+    let events = daemon.resolve_hostname(&hostname, Some(wait.as_millis() as u64))?;
+
+    let mut found = Vec::new();
+    let deadline = Instant::now() + wait;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match events.recv_timeout(left) {
+            Ok(HostnameResolutionEvent::AddressesFound(_, addrs)) => {
+                // See `pick_addr` on link-local addresses
+                found.extend(addrs.into_iter().filter(|ip| match ip {
+                    IpAddr::V6(v6) => !is_link_local(v6),
+                    IpAddr::V4(_) => true,
+                }));
+                if !found.is_empty() {
+                    break;
+                }
+            }
+            Ok(HostnameResolutionEvent::SearchTimeout(_)) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+
+    log::debug!("Resolved {} via mDNS to {:?}", hostname, found);
+
+    let _ = daemon.shutdown();
+    Ok(found)
+}
+
+/// Prefer IPv4. Link-local IPv6 addresses are useless here, as mDNS does
+/// not tell on which of our interfaces they are valid.
+fn pick_addr(info: &ServiceInfo) -> Option<IpAddr> {
+    let addresses = info.get_addresses();
+    addresses
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or_else(|| {
+            addresses.iter().find(|ip| match ip {
+                IpAddr::V6(v6) => !is_link_local(v6),
+                IpAddr::V4(_) => false,
+            })
+        })
+        .copied()
+}
@@ -6,6 +6,7 @@ use std::path::PathBuf;
 /// cache directory, and config directory.
 /// # Examples
 /// ```
+/// # use deliver::pkg_info::PkgInfo;
 /// let pi = PkgInfo::new();
 /// println!("Package Name: {}", pi.get_pkg_name());
 /// println!("Package Version: {}", pi.get_pkg_version());
@@ -17,6 +18,12 @@ pub struct PkgInfo {
     pkg_authors: &'static str,
 }
 
+impl Default for PkgInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PkgInfo {
     pub fn new() -> Self {
         Self {
@@ -27,15 +34,15 @@ impl PkgInfo {
     }
 
     pub fn get_pkg_name(&self) -> &str {
-        &self.pkg_name
+        self.pkg_name
     }
 
     pub fn get_pkg_version(&self) -> &str {
-        &self.pkg_version
+        self.pkg_version
     }
 
     pub fn get_pkg_authors(&self) -> &str {
-        &self.pkg_authors
+        self.pkg_authors
     }
 
     pub fn get_cache_dir(&self) -> PathBuf {
@@ -63,7 +70,7 @@ impl PkgInfo {
             }
         };
 
-        cache_dir.push(&self.pkg_name);
+        cache_dir.push(self.pkg_name);
         cache_dir
     }
 
@@ -92,7 +99,7 @@ impl PkgInfo {
             }
         };
 
-        config_dir.push(&self.pkg_name);
+        config_dir.push(self.pkg_name);
         config_dir
     }
-}
\ No newline at end of file
+}
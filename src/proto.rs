@@ -0,0 +1,234 @@
+//! The wire protocol spoken between sender and receiver.
+//!
+//! Control messages are exchanged as frames: a big-endian `u32` length
+//! followed by that many bytes of JSON. The file content itself is sent raw
+//! right after the receiver accepted the header, followed by a `Trailer`
+//! with its checksum, so the sender reads the file only once.
+//!
+//! ```text
+//! receiver -> sender   Queued, repeated while waiting for a free slot
+//! receiver -> sender   Hello { version, device, nonce } | Busy { retry_after }
+//! sender -> receiver   Identify { device, nonce, signature }
+//! sender -> receiver   Header { name, size, text, contents } | Text { text }
+//! receiver -> sender   Queued, repeated while its user decides
+//! receiver -> sender   Accept { signature } | Reject { reason }
+//! sender -> receiver   <size bytes of content>
+//! sender -> receiver   Trailer { checksum }
+//! receiver -> sender   Queued, repeated while it saves the content
+//! receiver -> sender   Done | Failed { failure, reason }
+//! ```
+//!
+//! A short text snippet travels inside its `Text` frame, so no content
+//! or trailer follows it. Longer snippets are announced with a `Header` marked `text`.
+//! A directory is sent as an archive, whose `Header` says how many files it
+//! holds and how large they are, so the receiver knows before accepting.
+//!
+//! If the receiver gives up on a transfer while the content is still
+//! arriving, e.g. because it stalled, it sends `Failed` right away and closes
+//! the connection.
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::identity::Device;
+
+pub const PROTOCOL_VERSION: u16 = 4;
+
+/// Payload kinds this version of the receiver accepts, as advertised on the LAN.
+pub const CAPABILITIES: &[&str] = &["file", "directory", "text"];
+
+/// Snippets up to this many bytes are sent inline in a `Text` frame.
+pub const COMPACT_TEXT_LEN: usize = 8 * 1024;
+
+/// The largest text snippet a receiver takes.
+pub const MAX_TEXT_LEN: usize = 1024 * 1024;
+
+/// The name text snippets are transferred and recorded under.
+pub const SNIPPET_NAME: &str = "snippet";
+
+/// Upper bound for a single control frame, so a broken peer cannot make us
+/// allocate arbitrary amounts of memory.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Frame {
+    /// First frame of every connection, sent by the receiver.
+    Hello {
+        version: u16,
+        device: Device,
+        /// Challenge the sender has to sign.
+        nonce: String,
+    },
+    /// Sent instead of `Hello` when the receiver is over its connection limits.
+    /// The sender should back off and retry after `retry_after` seconds.
+    Busy {
+        retry_after: u64,
+    },
+    /// Keepalive while the connection waits in the receiver's queue, for
+    /// the receiver's user to accept the header, or for the content to be saved.
+    Queued,
+    /// The sender's identity and its signature over the receiver's nonce.
+    Identify {
+        device: Device,
+        /// Challenge the receiver has to sign when accepting.
+        nonce: String,
+        signature: String,
+    },
+    Header {
+        name: String,
+        size: u64,
+        /// The content is a text snippet rather than a file.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        text: bool,
+        /// What a directory archive holds.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        contents: Option<Contents>,
+    },
+    /// A short text snippet, sent instead of a `Header` and its content.
+    Text {
+        text: String,
+    },
+    /// Follows the content.
+    Trailer {
+        /// Hex encoded SHA256 of the content.
+        checksum: String,
+    },
+    /// The receiver takes the transfer and proves its identity.
+    Accept {
+        signature: String,
+    },
+    Reject {
+        reason: String,
+    },
+    /// The content arrived and matches the checksum.
+    Done,
+    /// The transfer failed after it was accepted.
+    Failed {
+        failure: Failure,
+        reason: String,
+    },
+}
+
+/// The files in a directory archive, as left after the sender's exclusions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Contents {
+    pub files: u64,
+    /// Their total size in bytes, before archiving.
+    pub size: u64,
+}
+
+/// How a transfer failed after the receiver accepted it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// The connection ended before all announced bytes and their checksum
+    /// arrived.
+    Truncated,
+    /// More bytes arrived than were announced.
+    Oversized,
+    /// All bytes arrived, but they do not match the checksum.
+    Corrupt,
+    /// The sender reset the connection.
+    Cancelled,
+    /// No data arrived for too long, or it arrived too slowly.
+    TimedOut,
+    /// The receiver gave up, e.g. because its user cancelled the transfer.
+    Aborted,
+}
+
+impl Failure {
+    /// The name used on the wire and in the history file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Failure::Truncated => "truncated",
+            Failure::Oversized => "oversized",
+            Failure::Corrupt => "corrupt",
+            Failure::Cancelled => "cancelled",
+            Failure::TimedOut => "timed_out",
+            Failure::Aborted => "aborted",
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Truncated => write!(f, "truncated"),
+            Failure::Oversized => write!(f, "oversized"),
+            Failure::Corrupt => write!(f, "corrupt"),
+            Failure::Cancelled => write!(f, "cancelled by the sender"),
+            Failure::TimedOut => write!(f, "timed out"),
+            Failure::Aborted => write!(f, "aborted by the receiver"),
+        }
+    }
+}
+
+/// A transfer that failed in a known way, so callers can tell failures
+/// apart with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub struct TransferFailed {
+    pub failure: Failure,
+    pub reason: String,
+}
+
+impl fmt::Display for TransferFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Transfer {}: {}", self.failure, self.reason)
+    }
+}
+
+impl std::error::Error for TransferFailed {}
+
+impl Frame {
+    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let body = serde_json::to_vec(self)?;
+        let mut buf = (body.len() as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(&body);
+        Ok(buf)
+    }
+
+    fn decode_len(len_buf: [u8; 4]) -> anyhow::Result<usize> {
+        let len = u32::from_be_bytes(len_buf) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(anyhow::anyhow!("Frame too large: {} bytes", len));
+        }
+        Ok(len)
+    }
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, frame: &Frame) -> anyhow::Result<()> {
+    writer.write_all(&frame.encode()?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_frame<R: Read>(reader: &mut R) -> anyhow::Result<Frame> {
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf)?;
+
+    let mut body = vec![0u8; Frame::decode_len(len_buf)?];
+    reader.read_exact(&mut body)?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    frame: &Frame,
+) -> anyhow::Result<()> {
+    writer.write_all(&frame.encode()?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn read_frame_async<R: AsyncRead + Unpin>(reader: &mut R) -> anyhow::Result<Frame> {
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf).await?;
+
+    let mut body = vec![0u8; Frame::decode_len(len_buf)?];
+    reader.read_exact(&mut body).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Timeouts shared by sender and receiver, in seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// Establishing the TCP connection.
+    pub connect: u64,
+    /// Each step of the handshake, up to the receiver's answer.
+    pub header: u64,
+    /// The longest gap without any data while transferring.
+    pub idle: u64,
+    /// Bytes per second below which a transfer counts as stalled, 0 disables the check.
+    pub min_throughput: u64,
+    /// The window over which the throughput is averaged.
+    pub stall_window: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: 10,
+            header: 30,
+            idle: 30,
+            min_throughput: 1024,
+            stall_window: 20,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn connect(&self) -> Duration {
+        Duration::from_secs(self.connect)
+    }
+
+    pub fn header(&self) -> Duration {
+        Duration::from_secs(self.header)
+    }
+
+    pub fn idle(&self) -> Duration {
+        Duration::from_secs(self.idle)
+    }
+
+    /// Replace the timeouts set to 0, which would fail every wait right
+    /// away, with their defaults, logging each.
+    pub fn or_defaults(mut self) -> Self {
+        let defaults = Self::default();
+        for (name, value, default) in [
+            ("connect", &mut self.connect, defaults.connect),
+            ("header", &mut self.header, defaults.header),
+            ("idle", &mut self.idle, defaults.idle),
+        ] {
+            if *value == 0 {
+                log::error!(
+                    "The {} timeout must be at least 1 second, using {}s",
+                    name,
+                    default
+                );
+                *value = default;
+            }
+        }
+        self
+    }
+}
+
+/// The error of a transfer that took too long or became too slow, so
+/// callers can tell it apart with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub struct TimedOut(pub String);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Run `fut`, failing with a readable [`TimedOut`] error if it takes longer
+/// than `limit`.
+/// # Arguments
+/// * `what` - What we were doing, e.g. "waiting for the sender's header".
+pub async fn within<T>(
+    limit: Duration,
+    what: &str,
+    fut: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    tokio::time::timeout(limit, fut)
+        .await
+        .map_err(|_| TimedOut(format!("Timed out after {}s {}", limit.as_secs(), what)))?
+}
+
+/// Detects transfers that still make progress, but too slowly to be useful.
+/// # Example
+/// ```
+/// # use deliver::timeouts::{StallDetector, Timeouts};
+/// let mut stall = StallDetector::new(&Timeouts::default());
+/// stall.record(8192)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct StallDetector {
+    min_throughput: u64,
+    window: Duration,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl StallDetector {
+    pub fn new(timeouts: &Timeouts) -> Self {
+        Self {
+            min_throughput: timeouts.min_throughput,
+            window: Duration::from_secs(timeouts.stall_window.max(1)),
+            window_start: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+
+    /// Record `n` transferred bytes.
+    /// Fails with [`TimedOut`] once a whole window went by below the minimum
+    /// throughput.
+    pub fn record(&mut self, n: usize) -> anyhow::Result<()> {
+        self.window_bytes += n as u64;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed < self.window {
+            return Ok(());
+        }
+
+        let rate = self.window_bytes as f64 / elapsed.as_secs_f64();
+        if self.min_throughput > 0 && rate < self.min_throughput as f64 {
+            return Err(TimedOut(format!(
+                "Transfer stalled at {:.0} B/s over the last {}s (minimum {} B/s)",
+                rate,
+                elapsed.as_secs(),
+                self.min_throughput
+            ))
+            .into());
+        }
+
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+        Ok(())
+    }
+}
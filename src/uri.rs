@@ -0,0 +1,126 @@
+//! `deliver://` URIs, which hold everything needed to reach a receiver.
+//!
+//! ```text
+//! deliver://192.168.1.20:9000?fp=SHA256:7f9ea3843dec55a2e6319b1fd00a573a
+//! deliver://[fe80::1%eth0]:9000
+//! ```
+
+use std::fmt;
+
+use crate::addr::{join_host_port, split_host_port};
+
+pub const SCHEME: &str = "deliver://";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliverUri {
+    pub host: String,
+    pub port: u16,
+    /// The receiver's device fingerprint, to be checked in the handshake.
+    pub fingerprint: Option<String>,
+}
+
+impl DeliverUri {
+    /// Parse a `deliver://` URI, or `None` if `s` is not one.
+    /// # Example
+    /// ```
+    /// # use deliver::uri::DeliverUri;
+    /// let uri = DeliverUri::parse("deliver://10.0.0.2:9000?fp=SHA256:1f0c").unwrap();
+    /// assert_eq!(uri.host, "10.0.0.2");
+    /// assert_eq!(uri.port, 9000);
+    /// assert_eq!(uri.fingerprint.as_deref(), Some("SHA256:1f0c"));
+    /// ```
+    pub fn parse(s: &str) -> Option<Self> {
+        let rest = s.trim().strip_prefix(SCHEME)?;
+        let (authority, query) = match rest.split_once('?') {
+            Some((authority, query)) => (authority, Some(query)),
+            None => (rest, None),
+        };
+        let (host, port) = split_host_port(authority.trim_end_matches('/'))?;
+
+        let fingerprint = query.and_then(|query| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == "fp")
+                .map(|(_, value)| value.to_string())
+        });
+
+        Some(Self {
+            host,
+            port,
+            fingerprint,
+        })
+    }
+}
+
+impl fmt::Display for DeliverUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, join_host_port(&self.host, self.port))?;
+        if let Some(fingerprint) = &self.fingerprint {
+            write!(f, "?fp={}", fingerprint)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(host: &str, port: u16, fingerprint: Option<&str>) -> DeliverUri {
+        DeliverUri {
+            host: host.to_string(),
+            port,
+            fingerprint: fingerprint.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn display_and_parse_round_trip() {
+        for uri in [
+            uri("10.0.0.2", 9000, None),
+            uri(
+                "laptop.local",
+                9000,
+                Some("SHA256:7f9ea3843dec55a2e6319b1fd00a573a"),
+            ),
+            uri("::1", 1, None),
+            uri("fe80::1%eth0", 65535, Some("SHA256:1f0c")),
+        ] {
+            let text = uri.to_string();
+            assert_eq!(DeliverUri::parse(&text), Some(uri), "{}", text);
+        }
+        assert_eq!(
+            uri("fe80::1%eth0", 9000, None).to_string(),
+            "deliver://[fe80::1%eth0]:9000"
+        );
+    }
+
+    #[test]
+    fn parse_tolerates_trailing_slash_and_other_keys() {
+        assert_eq!(
+            DeliverUri::parse(" deliver://10.0.0.2:9000/?v=1&fp=SHA256:1f0c\n"),
+            Some(uri("10.0.0.2", 9000, Some("SHA256:1f0c")))
+        );
+        assert_eq!(
+            DeliverUri::parse("deliver://10.0.0.2:9000?v=1"),
+            Some(uri("10.0.0.2", 9000, None))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_other_strings() {
+        for s in [
+            "",
+            "10.0.0.2:9000",
+            "http://10.0.0.2:9000",
+            "deliver://",
+            "deliver://10.0.0.2",
+            "deliver://10.0.0.2:port",
+            "deliver://::1:9000",
+            "deliver://[::1]",
+        ] {
+            assert_eq!(DeliverUri::parse(s), None, "{:?}", s);
+        }
+    }
+}